@@ -0,0 +1,121 @@
+// Packs the initial ramdisk the kernel unpacks at boot. Each entry is a
+// little-endian u32 path length, the path, a u32 data length and the data,
+// after an 8 byte magic.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const INITRD_MAGIC: &[u8; 8] = b"INITRD1\0";
+
+// Sample programs built from the runtime crate's examples.
+const RUNTIME_EXAMPLES: &[&str] = &["hello", "edit", "cube"];
+
+// Where user programs are linked: the start of the address range the kernel
+// leaves to processes.
+const USER_BASE: u64 = 0x80_0000_0000;
+
+// Hand-assembled test programs for what the runtime won't do on purpose:
+// spinning without system calls and touching kernel memory.
+const PROGRAMS: &[(&str, &[u8])] = &[
+    (
+        "/bin/spin",
+        &[
+            0x48, 0xff, 0x44, 0x24, 0xf8, // inc qword [rsp - 8]
+            0xf3, 0x90, // pause
+            0xeb, 0xf7, // jmp to the start
+        ],
+    ),
+    (
+        "/bin/fault",
+        &[
+            0x48, 0xb8, 0x00, 0x00, 0x44, 0x44, 0x44, 0x44, 0x00, 0x00, // mov rax, HEAP_START
+            0x48, 0xc7, 0x00, 0x01, 0x00, 0x00, 0x00, // mov qword [rax], 1
+            0xeb, 0xfe, // jmp to itself
+        ],
+    ),
+];
+
+// A static executable with one read-only, executable segment holding the
+// headers and the code right after them.
+fn elf_image(code: &[u8]) -> Vec<u8> {
+    const HEADER_SIZE: u64 = 64;
+    const PROGRAM_HEADER_SIZE: u64 = 56;
+    let code_offset = HEADER_SIZE + PROGRAM_HEADER_SIZE;
+    let file_size = code_offset + code.len() as u64;
+
+    let mut image = Vec::new();
+    image.extend_from_slice(b"\x7fELF");
+    image.extend_from_slice(&[2, 1, 1, 0]); // 64-bit, little-endian, version 1, System V
+    image.extend_from_slice(&[0; 8]);
+    image.extend_from_slice(&2u16.to_le_bytes()); // executable
+    image.extend_from_slice(&0x3eu16.to_le_bytes()); // x86_64
+    image.extend_from_slice(&1u32.to_le_bytes());
+    image.extend_from_slice(&(USER_BASE + code_offset).to_le_bytes()); // entry
+    image.extend_from_slice(&HEADER_SIZE.to_le_bytes()); // program headers
+    image.extend_from_slice(&0u64.to_le_bytes()); // section headers
+    image.extend_from_slice(&0u32.to_le_bytes()); // flags
+    image.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+    image.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    image.extend_from_slice(&1u16.to_le_bytes());
+    image.extend_from_slice(&64u16.to_le_bytes());
+    image.extend_from_slice(&0u16.to_le_bytes());
+    image.extend_from_slice(&0u16.to_le_bytes());
+
+    image.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+    image.extend_from_slice(&5u32.to_le_bytes()); // readable and executable
+    image.extend_from_slice(&0u64.to_le_bytes()); // file offset
+    image.extend_from_slice(&USER_BASE.to_le_bytes()); // virtual address
+    image.extend_from_slice(&USER_BASE.to_le_bytes()); // physical address
+    image.extend_from_slice(&file_size.to_le_bytes());
+    image.extend_from_slice(&file_size.to_le_bytes());
+    image.extend_from_slice(&0x1000u64.to_le_bytes()); // alignment
+
+    image.extend_from_slice(code);
+    image
+}
+
+// Builds the runtime examples with their own target directory, since the
+// outer build holds the lock on ours. User code lives far from the kernel's
+// top 2 GiB, so it needs the large code model.
+fn build_runtime_examples(out_dir: &Path) -> PathBuf {
+    let target_dir = out_dir.join("runtime");
+    let status = Command::new(env::var("CARGO").unwrap())
+        .args(["build", "--package", "runtime", "--examples", "--release"])
+        .args(["--target", "x86_64-unknown-none"])
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .env("CARGO_ENCODED_RUSTFLAGS", "-Crelocation-model=static\x1f-Ccode-model=large\x1f-Cstrip=symbols")
+        .env_remove("RUSTFLAGS")
+        .env_remove("RUSTC_WORKSPACE_WRAPPER")
+        .status()
+        .expect("failed to run cargo for the runtime examples");
+    assert!(status.success(), "building the runtime examples failed");
+    target_dir.join("x86_64-unknown-none/release/examples")
+}
+
+fn add_file(initrd: &mut Vec<u8>, path: &str, data: &[u8]) {
+    initrd.extend_from_slice(&(path.len() as u32).to_le_bytes());
+    initrd.extend_from_slice(path.as_bytes());
+    initrd.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    initrd.extend_from_slice(data);
+}
+
+fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let examples = build_runtime_examples(&out_dir);
+
+    let mut initrd = INITRD_MAGIC.to_vec();
+    for name in RUNTIME_EXAMPLES {
+        let image = fs::read(examples.join(name)).unwrap();
+        add_file(&mut initrd, &format!("/bin/{}", name), &image);
+    }
+    for (path, code) in PROGRAMS {
+        add_file(&mut initrd, path, &elf_image(code));
+    }
+
+    fs::write(out_dir.join("initrd.img"), initrd).unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=runtime");
+}
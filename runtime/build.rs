@@ -0,0 +1,9 @@
+// User programs are static executables linked into the part of the address
+// space the kernel leaves to processes.
+const USER_BASE: u64 = 0x80_0000_0000;
+
+fn main() {
+    println!("cargo:rustc-link-arg-examples=--image-base={:#x}", USER_BASE);
+    println!("cargo:rustc-link-arg-examples=-static");
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,191 @@
+// The spinning wireframe cube from the kernel's graphics module, drawn into a
+// character grid and copied into a window a row at a time. Esc quits.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use runtime::{println, Window};
+
+runtime::entry!(main);
+
+const WIDTH: usize = 50;
+const HEIGHT: usize = 20;
+const PI: f32 = core::f32::consts::PI;
+
+#[derive(Clone, Copy)]
+struct Point3D {
+    x: f32,
+    y: f32,
+    z: f32,
+}
+
+const fn point(x: f32, y: f32, z: f32) -> Point3D {
+    Point3D { x, y, z }
+}
+
+const CUBE: [(Point3D, Point3D); 12] = [
+    (point(-1.0, -1.0, 1.0), point(1.0, -1.0, 1.0)),
+    (point(1.0, -1.0, 1.0), point(1.0, 1.0, 1.0)),
+    (point(1.0, 1.0, 1.0), point(-1.0, 1.0, 1.0)),
+    (point(-1.0, 1.0, 1.0), point(-1.0, -1.0, 1.0)),
+
+    (point(-1.0, -1.0, -1.0), point(1.0, -1.0, -1.0)),
+    (point(1.0, -1.0, -1.0), point(1.0, 1.0, -1.0)),
+    (point(1.0, 1.0, -1.0), point(-1.0, 1.0, -1.0)),
+    (point(-1.0, 1.0, -1.0), point(-1.0, -1.0, -1.0)),
+
+    (point(-1.0, -1.0, 1.0), point(-1.0, -1.0, -1.0)),
+    (point(1.0, -1.0, 1.0), point(1.0, -1.0, -1.0)),
+    (point(1.0, 1.0, 1.0), point(1.0, 1.0, -1.0)),
+    (point(-1.0, 1.0, 1.0), point(-1.0, 1.0, -1.0)),
+];
+
+// There is no libm, so a few terms of the Taylor series will do.
+fn sin(x: f32) -> f32 {
+    let x = x % (2.0 * PI);
+    let mut result = 0.0;
+    let mut term = x;
+    let mut i = 1;
+
+    for _ in 0..5 {
+        result += term;
+        i += 2;
+        term = -term * x * x / ((i - 1) * i) as f32;
+    }
+
+    result
+}
+
+fn cos(x: f32) -> f32 {
+    sin(x + PI / 2.0)
+}
+
+fn abs(x: f32) -> f32 {
+    if x < 0.0 { -x } else { x }
+}
+
+fn line_char(dx: f32, dy: f32) -> u8 {
+    let slope = if abs(dx) < 0.001 { 1000.0 } else { dy / dx };
+
+    if abs(slope) < 0.5 {
+        b'-'
+    } else if abs(slope) > 2.0 {
+        b'|'
+    } else if slope > 0.0 {
+        b'\\'
+    } else {
+        b'/'
+    }
+}
+
+struct Renderer {
+    rotation: (f32, f32, f32),
+    distance: f32,
+    columns: usize,
+    rows: usize,
+    grid: Vec<u8>,
+}
+
+impl Renderer {
+    fn new(columns: usize, rows: usize) -> Renderer {
+        Renderer {
+            rotation: (0.0, 0.0, 0.0),
+            distance: 5.0,
+            columns,
+            rows,
+            grid: vec![b' '; columns * rows],
+        }
+    }
+
+    fn rotate_point(&self, point: Point3D) -> Point3D {
+        let (rot_x, rot_y, rot_z) = self.rotation;
+
+        let y1 = point.y * cos(rot_x) - point.z * sin(rot_x);
+        let z1 = point.y * sin(rot_x) + point.z * cos(rot_x);
+
+        let x2 = point.x * cos(rot_y) + z1 * sin(rot_y);
+        let z2 = -point.x * sin(rot_y) + z1 * cos(rot_y);
+
+        let x3 = x2 * cos(rot_z) - y1 * sin(rot_z);
+        let y3 = x2 * sin(rot_z) + y1 * cos(rot_z);
+
+        Point3D { x: x3, y: y3, z: z2 }
+    }
+
+    fn project_point(&self, point: Point3D) -> (f32, f32) {
+        let rotated = self.rotate_point(point);
+        let z_offset = rotated.z + self.distance;
+        let scale = 2.5 / if z_offset < 0.1 { 0.1 } else { z_offset };
+        let x = (rotated.x * scale + 1.0) * 0.5 * (self.columns - 1) as f32;
+        let y = (rotated.y * scale + 1.0) * 0.5 * (self.rows - 1) as f32;
+        (x, y)
+    }
+
+    fn plot(&mut self, x: f32, y: f32, byte: u8) {
+        if x < 0.0 || y < 0.0 {
+            return;
+        }
+        let (x, y) = (x as usize, y as usize);
+        if x < self.columns && y < self.rows {
+            self.grid[y * self.columns + x] = byte;
+        }
+    }
+
+    fn draw_line(&mut self, from: (f32, f32), to: (f32, f32)) {
+        let dx = to.0 - from.0;
+        let dy = to.1 - from.1;
+        let byte = line_char(dx, dy);
+
+        let steps = if abs(dx) > abs(dy) { abs(dx) } else { abs(dy) } as usize + 1;
+        for i in 0..=steps {
+            let t = i as f32 / steps as f32;
+            self.plot(from.0 + dx * t, from.1 + dy * t, byte);
+        }
+    }
+
+    fn render(&mut self, window: &Window) {
+        self.grid.fill(b' ');
+        for (from, to) in CUBE {
+            let from = self.project_point(from);
+            let to = self.project_point(to);
+            self.draw_line(from, to);
+        }
+
+        for row in 0..self.rows {
+            let line = &self.grid[row * self.columns..(row + 1) * self.columns];
+            let line = String::from_utf8_lossy(line);
+            let _ = window.print_at(0, row, &line);
+        }
+    }
+
+    fn rotate(&mut self, dx: f32, dy: f32, dz: f32) {
+        let (rx, ry, rz) = self.rotation;
+        self.rotation = (rx + dx, ry + dy, rz + dz);
+    }
+}
+
+fn main() {
+    let window = match Window::new("3D Cube", WIDTH, HEIGHT) {
+        Ok(window) => window,
+        Err(_) => {
+            println!("cube: needs windowed mode");
+            return;
+        },
+    };
+    let mut renderer = Renderer::new(window.columns(), window.rows());
+
+    loop {
+        match window.poll_key() {
+            Ok(Some('\u{1b}')) | Err(_) => break,
+            _ => {},
+        }
+        renderer.render(&window);
+        renderer.rotate(0.05, 0.07, 0.03);
+        runtime::syscall::sleep(2);
+    }
+}
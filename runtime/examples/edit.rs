@@ -0,0 +1,87 @@
+// A small text editor in its own window. Typing appends at the end, Enter
+// starts a new line, Backspace deletes and Esc quits, printing the text to the
+// terminal.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use runtime::{println, Window};
+
+runtime::entry!(main);
+
+const WIDTH: usize = 60;
+const HEIGHT: usize = 20;
+
+struct Editor {
+    window: Window,
+    lines: Vec<String>,
+}
+
+impl Editor {
+    fn handle_key(&mut self, key: char) {
+        let line = self.lines.last_mut().unwrap();
+        match key {
+            '\n' => self.lines.push(String::new()),
+            '\u{8}' => {
+                if line.pop().is_none() && self.lines.len() > 1 {
+                    self.lines.pop();
+                }
+            },
+            '\t' => line.push_str("    "),
+            key if !key.is_control() => line.push(key),
+            _ => {},
+        }
+    }
+
+    // Shows the end of the text with a status line underneath.
+    fn draw(&self) {
+        let columns = self.window.columns();
+        let text_rows = self.window.rows() - 1;
+        let first = self.lines.len().saturating_sub(text_rows);
+        for row in 0..text_rows {
+            let line = self.lines.get(first + row).map_or("", String::as_str);
+            // Long lines scroll so their end stays visible.
+            let start = line.chars().count().saturating_sub(columns - 1);
+            let visible: String = line.chars().skip(start).collect();
+            self.print_row(row, &visible);
+        }
+
+        let status = format!("Esc: quit  {} line(s)", self.lines.len());
+        self.print_row(text_rows, &status);
+    }
+
+    fn print_row(&self, row: usize, text: &str) {
+        // Padded to clear whatever was there before.
+        let padded = format!("{:<1$}", text, self.window.columns());
+        let _ = self.window.print_at(0, row, &padded);
+    }
+}
+
+fn main() {
+    let window = match Window::new("Editor", WIDTH, HEIGHT) {
+        Ok(window) => window,
+        Err(_) => {
+            println!("edit: needs windowed mode");
+            return;
+        },
+    };
+    let mut editor = Editor { window, lines: vec![String::new()] };
+
+    loop {
+        editor.draw();
+        match editor.window.read_key() {
+            Ok('\u{1b}') | Err(_) => break,
+            Ok(key) => editor.handle_key(key),
+        }
+    }
+
+    for line in &editor.lines {
+        println!("{}", line);
+    }
+}
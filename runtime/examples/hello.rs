@@ -0,0 +1,19 @@
+#![no_std]
+#![no_main]
+
+use runtime::println;
+
+runtime::entry!(main);
+
+fn main() {
+    let mut args = runtime::args();
+    let name = args.next().unwrap_or("hello");
+    println!("Hello from user mode!");
+    println!("{} started with {} argument(s)", name, runtime::arg_count().saturating_sub(1));
+    for (i, arg) in args.enumerate() {
+        println!("  argv[{}] = {}", i + 1, arg);
+    }
+    if let Some(user) = runtime::var("USER") {
+        println!("USER is {}", user);
+    }
+}
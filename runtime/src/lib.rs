@@ -0,0 +1,151 @@
+// Runtime for programs started with `exec`: the entry point, system call
+// wrappers, printing to the terminal and a heap.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod syscall;
+pub mod window;
+
+use core::ffi::CStr;
+use core::fmt;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use linked_list_allocator::LockedHeap;
+
+pub use window::Window;
+
+const HEAP_SIZE: u64 = 64 * 1024;
+
+#[global_allocator]
+static ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+static ARGC: AtomicUsize = AtomicUsize::new(0);
+static ARGV: AtomicPtr<*const u8> = AtomicPtr::new(core::ptr::null_mut());
+static ENVP: AtomicPtr<*const u8> = AtomicPtr::new(core::ptr::null_mut());
+
+// Defines the program's entry point. The function runs once the heap and
+// arguments are set up, and the process exits when it returns.
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[no_mangle]
+        fn __runtime_main() {
+            $main()
+        }
+    };
+}
+
+extern "Rust" {
+    fn __runtime_main();
+}
+
+// The kernel starts programs with argc, argv and envp in rdi, rsi and rdx and
+// a 16 byte aligned stack, so call into Rust the way a call would.
+#[unsafe(naked)]
+#[no_mangle]
+unsafe extern "C" fn _start() -> ! {
+    core::arch::naked_asm!(
+        "and rsp, -16",
+        "call {start}",
+        "ud2",
+        start = sym start,
+    );
+}
+
+extern "C" fn start(argc: usize, argv: *mut *const u8, envp: *mut *const u8) -> ! {
+    ARGC.store(argc, Ordering::Relaxed);
+    ARGV.store(argv, Ordering::Relaxed);
+    ENVP.store(envp, Ordering::Relaxed);
+
+    if let Ok(heap) = syscall::map(HEAP_SIZE) {
+        unsafe { ALLOCATOR.lock().init(heap, HEAP_SIZE as usize) };
+    }
+
+    unsafe { __runtime_main() };
+    syscall::exit(0)
+}
+
+// Iterates over a null-terminated array of C strings, skipping any that
+// aren't UTF-8.
+#[derive(Clone)]
+pub struct Strings {
+    next: *const *const u8,
+}
+
+impl Iterator for Strings {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<&'static str> {
+        loop {
+            if self.next.is_null() {
+                return None;
+            }
+            let string = unsafe { *self.next };
+            if string.is_null() {
+                return None;
+            }
+            self.next = unsafe { self.next.add(1) };
+            let string = unsafe { CStr::from_ptr(string.cast()) };
+            if let Ok(string) = string.to_str() {
+                return Some(string);
+            }
+        }
+    }
+}
+
+// The program's arguments, starting with the name it was run as.
+pub fn args() -> Strings {
+    Strings { next: ARGV.load(Ordering::Relaxed) }
+}
+
+pub fn arg_count() -> usize {
+    ARGC.load(Ordering::Relaxed)
+}
+
+// Exported shell variables, as "NAME=VALUE".
+pub fn env() -> Strings {
+    Strings { next: ENVP.load(Ordering::Relaxed) }
+}
+
+pub fn var(name: &str) -> Option<&'static str> {
+    env().find_map(|entry| {
+        let (key, value) = entry.split_once('=')?;
+        (key == name).then_some(value)
+    })
+}
+
+struct Console;
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for chunk in s.as_bytes().chunks(syscall::MAX_TEXT_LENGTH) {
+            syscall::write(chunk).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    let _ = Console.write_fmt(args);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    println!("{}", info);
+    syscall::exit(101)
+}
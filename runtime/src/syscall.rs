@@ -0,0 +1,116 @@
+use core::arch::asm;
+
+// Must match the kernel's numbering in src/syscall.rs.
+pub const SYS_WRITE: u64 = 0;
+pub const SYS_READ_KEY: u64 = 1;
+pub const SYS_WINDOW_CREATE: u64 = 2;
+pub const SYS_WINDOW_PRINT: u64 = 3;
+pub const SYS_SLEEP: u64 = 4;
+pub const SYS_EXIT: u64 = 5;
+pub const SYS_MAP: u64 = 6;
+pub const SYS_POLL_KEY: u64 = 7;
+
+// The longest string the kernel accepts in one call.
+pub const MAX_TEXT_LENGTH: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    BadPointer,
+    InvalidArgument,
+    UnknownSyscall,
+    NoMemory,
+    Interrupted,
+    Other(u64),
+}
+
+impl Error {
+    fn from_code(code: u64) -> Error {
+        match code as i64 {
+            -1 => Error::BadPointer,
+            -2 => Error::InvalidArgument,
+            -3 => Error::UnknownSyscall,
+            -4 => Error::NoMemory,
+            -5 => Error::Interrupted,
+            _ => Error::Other(code),
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+// The kernel returns errors as small negative numbers and anything else is
+// the result.
+fn result(value: u64) -> Result<u64> {
+    if (value as i64) < 0 && (value as i64) >= -4096 {
+        Err(Error::from_code(value))
+    } else {
+        Ok(value)
+    }
+}
+
+// `syscall` clobbers rcx and r11; the kernel preserves every other register
+// apart from rax.
+unsafe fn syscall5(number: u64, a0: u64, a1: u64, a2: u64, a3: u64, a4: u64) -> u64 {
+    let value;
+    asm!(
+        "syscall",
+        inlateout("rax") number => value,
+        in("rdi") a0,
+        in("rsi") a1,
+        in("rdx") a2,
+        in("r10") a3,
+        in("r8") a4,
+        out("rcx") _,
+        out("r11") _,
+        options(nostack),
+    );
+    value
+}
+
+pub fn write(text: &[u8]) -> Result<usize> {
+    let value = unsafe { syscall5(SYS_WRITE, text.as_ptr() as u64, text.len() as u64, 0, 0, 0) };
+    result(value).map(|written| written as usize)
+}
+
+// Waits for a key typed into one of the process's windows.
+pub fn read_key(window: u64) -> Result<char> {
+    let value = unsafe { syscall5(SYS_READ_KEY, window, 0, 0, 0, 0) };
+    result(value).map(|key| char::from_u32(key as u32).unwrap_or('\0'))
+}
+
+// Like `read_key`, but doesn't wait when no key is waiting.
+pub fn poll_key(window: u64) -> Result<Option<char>> {
+    let value = unsafe { syscall5(SYS_POLL_KEY, window, 0, 0, 0, 0) };
+    result(value).map(|key| char::from_u32(key as u32).filter(|&key| key != '\0'))
+}
+
+pub fn window_create(title: &str, width: u64, height: u64) -> Result<u64> {
+    let value = unsafe {
+        syscall5(SYS_WINDOW_CREATE, title.as_ptr() as u64, title.len() as u64, width, height, 0)
+    };
+    result(value)
+}
+
+pub fn window_print(window: u64, x: u64, y: u64, text: &str) -> Result<()> {
+    let value = unsafe {
+        syscall5(SYS_WINDOW_PRINT, window, x, y, text.as_ptr() as u64, text.len() as u64)
+    };
+    result(value).map(|_| ())
+}
+
+pub fn sleep(ticks: u64) {
+    unsafe { syscall5(SYS_SLEEP, ticks, 0, 0, 0, 0) };
+}
+
+pub fn exit(status: u64) -> ! {
+    unsafe { syscall5(SYS_EXIT, status, 0, 0, 0, 0) };
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+// Maps zeroed, writable memory.
+pub fn map(size: u64) -> Result<*mut u8> {
+    let value = unsafe { syscall5(SYS_MAP, size, 0, 0, 0, 0) };
+    result(value).map(|start| start as *mut u8)
+}
@@ -0,0 +1,38 @@
+use crate::syscall::{self, Result};
+
+// A window owned by the process. It stays open until the process exits.
+pub struct Window {
+    id: u64,
+    width: usize,
+    height: usize,
+}
+
+impl Window {
+    // Only works in windowed mode. The size includes the border.
+    pub fn new(title: &str, width: usize, height: usize) -> Result<Window> {
+        let id = syscall::window_create(title, width as u64, height as u64)?;
+        Ok(Window { id, width, height })
+    }
+
+    // The space inside the border, which is what `print_at` positions are
+    // relative to.
+    pub fn columns(&self) -> usize {
+        self.width - 2
+    }
+
+    pub fn rows(&self) -> usize {
+        self.height - 2
+    }
+
+    pub fn print_at(&self, x: usize, y: usize, text: &str) -> Result<()> {
+        syscall::window_print(self.id, x as u64, y as u64, text)
+    }
+
+    pub fn read_key(&self) -> Result<char> {
+        syscall::read_key(self.id)
+    }
+
+    pub fn poll_key(&self) -> Result<Option<char>> {
+        syscall::poll_key(self.id)
+    }
+}
@@ -0,0 +1,13 @@
+# Boot configuration, installed as /etc/rc and run by the first terminal
+# when the kernel starts in windowed mode. Edit it and `source /etc/rc`
+# to try changes without rebuilding.
+
+window open 5 3 30 10 cyan Main Window | read main
+window print $main 1 3 "Window Manager Demo"
+
+window open 40 5 35 8 green System Info | read info
+window print $info 1 1 "CPU: x86_64"
+window print $info 1 2 "Memory: 64MB"
+window print $info 1 3 "Status: Running"
+
+echo "Type 'help' for available commands"
@@ -1,46 +1,742 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::config::{KeyboardLayout, Keymap};
+use crate::ramdisk::RAMDISK;
+use crate::shell::{is_valid_name, STATUS_FAILURE, STATUS_NOT_FOUND, STATUS_SUCCESS, STATUS_SYNTAX_ERROR};
 use crate::terminal::{Terminal, TerminalOutput};
+use crate::vga::Color;
+use crate::window_manager::{Layout, Window, WindowHandle, WINDOW_MANAGER};
+
+const COMMANDS: &[(&str, &str)] = &[
+    ("help", "Show this list"),
+    ("clear", "Clear the terminal"),
+    ("echo", "Print the given arguments"),
+    ("cat", "Print files or piped input"),
+    ("grep", "Print lines containing a pattern"),
+    ("wc", "Count lines, words and bytes"),
+    ("ls", "List a ramdisk directory"),
+    ("rm", "Remove files from the ramdisk"),
+    ("mkdir", "Create ramdisk directories"),
+    ("cd", "Change the current directory"),
+    ("pwd", "Print the current directory"),
+    ("set", "List or set shell variables"),
+    ("unset", "Remove shell variables"),
+    ("export", "Export variables to the environment"),
+    ("env", "List exported variables"),
+    ("uptime", "Show time since boot"),
+    ("ps", "List threads and their states"),
+    ("exec", "Start a user program from an ELF file"),
+    ("kill", "End a user process"),
+    ("source", "Run a script in this shell"),
+    ("sh", "Run a script in a child scope"),
+    ("exit", "Leave the running script"),
+    ("test", "Evaluate a condition"),
+    ("expr", "Evaluate integers with + - * / % and -eq -ne -lt -le -gt -ge"),
+    ("read", "Read piped input into variables"),
+    ("window", "Open, close, list or print to windows"),
+    ("layout", "Show or set floating, master or grid layout"),
+    ("true", "Succeed"),
+    ("false", "Fail"),
+    ("history", "List previously entered commands"),
+    ("info", "Show the OS version"),
+    ("mode", "Show the current UI mode"),
+    ("keymap", "Show or change the keyboard layout"),
+    ("term", "Open another terminal window"),
+    ("3d", "Launch the 3D demo window"),
+    ("sysmon", "Open the system monitor window"),
+    ("windowed", "Switch to windowed mode"),
+    ("fullscreen", "Switch to fullscreen terminal"),
+];
 
 impl Terminal {
-    pub fn execute_command(&mut self, command: &str) -> &'static str {
-        match command.trim() {
-            "help" => "Available commands: help, clear, info, mode, 3d, windowed, fullscreen",
+    pub fn execute_command(&mut self, argv: &[&str]) -> i32 {
+        let (name, args) = match argv.split_first() {
+            Some((name, args)) => (*name, args),
+            None => return STATUS_SUCCESS,
+        };
+
+        match name {
+            "help" => {
+                let _ = writeln!(self, "Available commands:");
+                for (name, description) in COMMANDS.iter() {
+                    let _ = writeln!(self, "  {:<12}{}", name, description);
+                }
+                let _ = writeln!(self, "Combine with ';', '&&', '||', '|', '>' and '>>'");
+                let _ = writeln!(self, "PS1 escapes: \\t uptime, \\m UI mode, \\w directory");
+                STATUS_SUCCESS
+            },
             "clear" => {
                 self.clear();
-                ""
+                STATUS_SUCCESS
+            },
+            "echo" => {
+                let _ = writeln!(self, "{}", args.join(" "));
+                STATUS_SUCCESS
+            },
+            "cat" => match self.read_input(args) {
+                Ok(input) => {
+                    let _ = write!(self, "{}", input);
+                    STATUS_SUCCESS
+                },
+                Err(status) => status,
+            },
+            "grep" => {
+                let (pattern, files) = match args.split_first() {
+                    Some((pattern, files)) => (*pattern, files),
+                    None => {
+                        let _ = writeln!(self, "usage: grep PATTERN [FILE...]");
+                        return STATUS_FAILURE;
+                    }
+                };
+                let input = match self.read_input(files) {
+                    Ok(input) => input,
+                    Err(status) => return status,
+                };
+                let mut status = STATUS_FAILURE;
+                for line in input.lines().filter(|line| line.contains(pattern)) {
+                    let _ = writeln!(self, "{}", line);
+                    status = STATUS_SUCCESS;
+                }
+                status
+            },
+            "wc" => match self.read_input(args) {
+                Ok(input) => {
+                    let lines = input.lines().count();
+                    let words = input.split_whitespace().count();
+                    let _ = writeln!(self, "{:>6} {:>6} {:>6}", lines, words, input.len());
+                    STATUS_SUCCESS
+                },
+                Err(status) => status,
+            },
+            "ls" => {
+                let dir = self.resolve_path(args.first().copied().unwrap_or("."));
+                if !RAMDISK.lock().is_dir(&dir) {
+                    let _ = writeln!(self, "ls: {}: no such directory", dir);
+                    return STATUS_FAILURE;
+                }
+                let entries = RAMDISK.lock().list(&dir);
+                for (name, size) in entries {
+                    match size {
+                        Some(size) => {
+                            let _ = writeln!(self, "{:>6}  {}", size, name);
+                        },
+                        None => {
+                            let _ = writeln!(self, "{:>6}  {}/", "<dir>", name);
+                        },
+                    }
+                }
+                STATUS_SUCCESS
+            },
+            "rm" => {
+                let mut status = STATUS_SUCCESS;
+                for file in args {
+                    let path = self.resolve_path(file);
+                    if !RAMDISK.lock().remove(&path) {
+                        let _ = writeln!(self, "rm: {}: no such file", file);
+                        status = STATUS_FAILURE;
+                    }
+                }
+                status
+            },
+            "mkdir" => {
+                let mut status = STATUS_SUCCESS;
+                for dir in args {
+                    let path = self.resolve_path(dir);
+                    if !RAMDISK.lock().mkdir(&path) {
+                        let _ = writeln!(self, "mkdir: cannot create {}", dir);
+                        status = STATUS_FAILURE;
+                    }
+                }
+                status
+            },
+            "cd" => {
+                let path = self.resolve_path(args.first().copied().unwrap_or("/"));
+                if RAMDISK.lock().is_dir(&path) {
+                    self.cwd = path;
+                    STATUS_SUCCESS
+                } else {
+                    let _ = writeln!(self, "cd: {}: no such directory", path);
+                    STATUS_FAILURE
+                }
+            },
+            "pwd" => {
+                let _ = writeln!(self, "{}", self.cwd.clone());
+                STATUS_SUCCESS
+            },
+            "set" => {
+                if args.is_empty() {
+                    let vars: Vec<(String, String)> = self
+                        .vars
+                        .iter()
+                        .map(|(name, var)| (name.clone(), var.value.clone()))
+                        .collect();
+                    for (name, value) in vars {
+                        let _ = writeln!(self, "{}={}", name, value);
+                    }
+                    return STATUS_SUCCESS;
+                }
+                match args {
+                    [assignment] if assignment.contains('=') => self.assign(assignment),
+                    [name, value @ ..] if is_valid_name(name) => {
+                        self.set_var(name, &value.join(" "));
+                        STATUS_SUCCESS
+                    },
+                    _ => {
+                        let _ = writeln!(self, "usage: set NAME=VALUE | set NAME VALUE...");
+                        STATUS_FAILURE
+                    },
+                }
+            },
+            "unset" => {
+                let mut status = STATUS_SUCCESS;
+                for name in args {
+                    if !self.unset_var(name) {
+                        status = STATUS_FAILURE;
+                    }
+                }
+                status
+            },
+            "export" => {
+                let mut status = STATUS_SUCCESS;
+                for arg in args {
+                    let name = match arg.split_once('=') {
+                        Some((name, _)) => name,
+                        None => arg,
+                    };
+                    if !is_valid_name(name) || (arg.contains('=') && self.assign(arg) != STATUS_SUCCESS) {
+                        let _ = writeln!(self, "export: {}: not a valid name", arg);
+                        status = STATUS_FAILURE;
+                        continue;
+                    }
+                    self.export_var(name);
+                }
+                status
+            },
+            "env" => {
+                let vars: Vec<(String, String)> = self
+                    .vars
+                    .iter()
+                    .filter(|(_, var)| var.exported)
+                    .map(|(name, var)| (name.clone(), var.value.clone()))
+                    .collect();
+                for (name, value) in vars {
+                    let _ = writeln!(self, "{}={}", name, value);
+                }
+                STATUS_SUCCESS
+            },
+            "uptime" => {
+                let seconds = crate::time::uptime_seconds();
+                let _ = writeln!(
+                    self,
+                    "up {}:{:02}:{:02} ({} ticks)",
+                    seconds / 3600,
+                    seconds / 60 % 60,
+                    seconds % 60,
+                    crate::time::ticks()
+                );
+                STATUS_SUCCESS
+            },
+            "ps" => {
+                let _ = writeln!(self, "{:>3} {:<6} {:<8} {:<9} {:>7} NAME", "ID", "MODE", "PRIO", "STATE", "TICKS");
+                for info in crate::thread::list().iter().flatten() {
+                    let _ = writeln!(
+                        self,
+                        "{:>3} {:<6} {:<8} {:<9} {:>7} {}",
+                        info.id.as_u64(),
+                        if info.user { "user" } else { "kernel" },
+                        info.priority.name(),
+                        info.state.name(),
+                        info.ticks,
+                        info.name.as_str()
+                    );
+                }
+                STATUS_SUCCESS
+            },
+            "exec" => {
+                let (program, _) = match args.split_first() {
+                    Some(split) => split,
+                    None => {
+                        let _ = writeln!(self, "usage: exec PROGRAM [ARG...]");
+                        return STATUS_SYNTAX_ERROR;
+                    },
+                };
+                // Bare names are looked up in /bin.
+                let path = if program.contains('/') {
+                    self.resolve_path(program)
+                } else {
+                    format!("/bin/{}", program)
+                };
+                let image = match RAMDISK.lock().read(&path) {
+                    Some(data) => data.to_vec(),
+                    None => {
+                        let _ = writeln!(self, "exec: {}: not found", program);
+                        return STATUS_NOT_FOUND;
+                    },
+                };
+                let env: Vec<String> = self
+                    .vars
+                    .iter()
+                    .filter(|(_, var)| var.exported)
+                    .map(|(name, var)| format!("{}={}", name, var.value))
+                    .collect();
+                let name = crate::ramdisk::file_name(&path);
+                match crate::process::exec(name, &image, args, &env, Some(self.binding())) {
+                    Ok(id) => {
+                        let _ = writeln!(self, "Started {} as process {}", name, id.as_u64());
+                        STATUS_SUCCESS
+                    },
+                    Err(error) => {
+                        let _ = writeln!(self, "exec: {}: {}", program, error);
+                        STATUS_FAILURE
+                    },
+                }
+            },
+            "kill" => match args {
+                [id] => match id.parse::<u64>() {
+                    Ok(id) if crate::process::kill(crate::thread::ThreadId::from_u64(id)) => STATUS_SUCCESS,
+                    Ok(id) => {
+                        let _ = writeln!(self, "kill: no user process {}", id);
+                        STATUS_FAILURE
+                    },
+                    Err(_) => {
+                        let _ = writeln!(self, "kill: invalid process id '{}'", id);
+                        STATUS_FAILURE
+                    },
+                },
+                _ => {
+                    let _ = writeln!(self, "usage: kill ID");
+                    STATUS_SYNTAX_ERROR
+                },
+            },
+            "source" | "." | "sh" => {
+                let path = match args.first() {
+                    Some(path) => self.resolve_path(path),
+                    None => {
+                        let _ = writeln!(self, "usage: {} FILE [ARG...]", name);
+                        return STATUS_FAILURE;
+                    }
+                };
+                let source = match RAMDISK.lock().read(&path) {
+                    Some(data) => String::from_utf8_lossy(data).into_owned(),
+                    None => {
+                        let _ = writeln!(self, "{}: {}: no such file", name, path);
+                        return STATUS_FAILURE;
+                    }
+                };
+                let script_args = args.iter().map(|&arg| String::from(arg)).collect();
+
+                if name == "sh" {
+                    let saved_vars = self.vars.clone();
+                    let saved_cwd = self.cwd.clone();
+                    let status = self.run_script(&source, script_args);
+                    self.vars = saved_vars;
+                    self.cwd = saved_cwd;
+                    status
+                } else {
+                    self.run_script(&source, script_args)
+                }
+            },
+            "exit" => {
+                let status = match args.first().map(|arg| arg.parse::<i32>()) {
+                    Some(Ok(status)) => status,
+                    Some(Err(_)) => {
+                        let _ = writeln!(self, "exit: numeric argument required");
+                        return STATUS_FAILURE;
+                    },
+                    None => self.last_status,
+                };
+                if self.script_depth == 0 {
+                    let _ = writeln!(self, "exit: not running a script");
+                    return STATUS_FAILURE;
+                }
+                self.exit_status = Some(status);
+                status
+            },
+            "test" => self.evaluate_test(args),
+            "[" => match args.split_last() {
+                Some((&"]", args)) => self.evaluate_test(args),
+                _ => {
+                    let _ = writeln!(self, "[: missing ']'");
+                    STATUS_SYNTAX_ERROR
+                },
+            },
+            "expr" => match evaluate_expr(args) {
+                Some(value) => {
+                    let _ = writeln!(self, "{}", value);
+                    if value != 0 { STATUS_SUCCESS } else { STATUS_FAILURE }
+                },
+                None => {
+                    let _ = writeln!(self, "expr: invalid expression");
+                    STATUS_SYNTAX_ERROR
+                },
             },
-            "info" => "OS Version 0.1.0",
-            "mode" => match self.output {
-                TerminalOutput::Windowed(_) => "UI Mode: Windowed",
-                TerminalOutput::Fullscreen => "UI Mode: Fullscreen Terminal",
+            "read" => {
+                let input = match self.stdin.take() {
+                    Some(input) => input,
+                    None => return STATUS_FAILURE,
+                };
+                let line = input.lines().next().unwrap_or("");
+                let mut fields = line.split_whitespace();
+                for (i, var) in args.iter().enumerate() {
+                    let value = if i + 1 == args.len() {
+                        let rest: Vec<&str> = fields.by_ref().collect();
+                        rest.join(" ")
+                    } else {
+                        String::from(fields.next().unwrap_or(""))
+                    };
+                    self.set_var(var, &value);
+                }
+                STATUS_SUCCESS
+            },
+            "window" => self.window_command(args),
+            "layout" => {
+                if let TerminalOutput::Fullscreen(_) = self.output {
+                    let _ = writeln!(self, "layout only available in windowed mode");
+                    return STATUS_FAILURE;
+                }
+                match args {
+                    [] => {
+                        let layout = WINDOW_MANAGER.lock().layout();
+                        let _ = writeln!(self, "Layout: {}", layout.name());
+                        STATUS_SUCCESS
+                    },
+                    [name] => match Layout::from_name(name) {
+                        Some(layout) => {
+                            crate::window_manager::set_layout(layout);
+                            STATUS_SUCCESS
+                        },
+                        None => {
+                            let _ = writeln!(self, "layout: unknown layout '{}'", name);
+                            STATUS_FAILURE
+                        },
+                    },
+                    _ => {
+                        let _ = writeln!(self, "usage: layout [floating|master|grid]");
+                        STATUS_FAILURE
+                    },
+                }
+            },
+            "true" => STATUS_SUCCESS,
+            "false" => STATUS_FAILURE,
+            "history" => {
+                let entries: Vec<String> = self
+                    .history()
+                    .map(|entry| entry.iter().take_while(|&&c| c != '\0').collect())
+                    .collect();
+                for (i, entry) in entries.iter().enumerate().rev() {
+                    let _ = writeln!(self, "{:>3}  {}", entries.len() - i, entry);
+                }
+                STATUS_SUCCESS
+            },
+            "info" => {
+                let _ = writeln!(self, "OS Version {}", env!("CARGO_PKG_VERSION"));
+                STATUS_SUCCESS
+            },
+            "mode" => {
+                let _ = writeln!(self, "UI Mode: {}", crate::config::get_current_ui_mode().name());
+                STATUS_SUCCESS
+            },
+            "keymap" => match args {
+                [] => {
+                    let name = crate::config::with_keymap(|keymap| keymap.name.clone());
+                    let layouts: Vec<&str> = KeyboardLayout::ALL.iter().map(|layout| layout.name()).collect();
+                    let _ = writeln!(self, "Keymap: {}", name);
+                    let _ = writeln!(self, "Layouts: {} or a keymap file", layouts.join(" "));
+                    STATUS_SUCCESS
+                },
+                [name] => {
+                    let keymap = match KeyboardLayout::from_name(name) {
+                        Some(layout) => Keymap::new(layout),
+                        None => {
+                            let path = self.resolve_path(name);
+                            let source = match RAMDISK.lock().read(&path) {
+                                Some(data) => String::from_utf8_lossy(data).into_owned(),
+                                None => {
+                                    let _ = writeln!(self, "keymap: {}: unknown layout or file", name);
+                                    return STATUS_FAILURE;
+                                }
+                            };
+                            match crate::keyboard::parse_keymap(&path, &source) {
+                                Ok(keymap) => keymap,
+                                Err(error) => {
+                                    let _ = writeln!(self, "keymap: {}: {}", path, error);
+                                    return STATUS_FAILURE;
+                                }
+                            }
+                        }
+                    };
+                    crate::config::set_keymap(keymap);
+                    STATUS_SUCCESS
+                },
+                _ => {
+                    let _ = writeln!(self, "usage: keymap [LAYOUT|FILE]");
+                    STATUS_FAILURE
+                },
+            },
+            "term" => {
+                if let TerminalOutput::Fullscreen(_) = self.output {
+                    let _ = writeln!(self, "Use Alt+F1..F6 to switch consoles in fullscreen mode");
+                    return STATUS_FAILURE;
+                }
+                match crate::terminal::open_terminal_window(Some(self)) {
+                    Some(_) => STATUS_SUCCESS,
+                    None => {
+                        let _ = writeln!(self, "term: cannot open another terminal");
+                        STATUS_FAILURE
+                    },
+                }
+            },
+            "sysmon" => {
+                if let TerminalOutput::Fullscreen(_) = self.output {
+                    let _ = writeln!(self, "sysmon only available in windowed mode");
+                    return STATUS_FAILURE;
+                }
+                match crate::sysmon::open() {
+                    Some(_) => STATUS_SUCCESS,
+                    None => {
+                        let _ = writeln!(self, "sysmon: too many windows");
+                        STATUS_FAILURE
+                    },
+                }
             },
             "3d" => {
                 if let TerminalOutput::Windowed(_) = self.output {
-                    let _ = crate::launch_3d_demo();
-                    "Launched 3D demo window"
+                    match crate::launch_3d_demo() {
+                        Ok(_) => {
+                            let _ = writeln!(self, "Launched 3D demo window");
+                            STATUS_SUCCESS
+                        },
+                        Err(error) => {
+                            let _ = writeln!(self, "3d: {}", error);
+                            STATUS_FAILURE
+                        },
+                    }
                 } else {
-                    "3D demo only available in windowed mode"
+                    let _ = writeln!(self, "3D demo only available in windowed mode");
+                    STATUS_FAILURE
                 }
             },
             "windowed" => {
-                if let TerminalOutput::Fullscreen = self.output {
+                if let TerminalOutput::Fullscreen(_) = self.output {
                     crate::config::set_ui_mode(crate::config::UiMode::Windowed);
-                    crate::vga::WRITER.lock().clear_screen();
-                    crate::_start();
+                    crate::restart_ui();
+                    STATUS_SUCCESS
                 } else {
-                    "Already in windowed mode"
+                    let _ = writeln!(self, "Already in windowed mode");
+                    STATUS_FAILURE
                 }
             },
             "fullscreen" => {
                 if let TerminalOutput::Windowed(_) = self.output {
                     crate::config::set_ui_mode(crate::config::UiMode::FullscreenTerminal);
-                    crate::vga::WRITER.lock().clear_screen();
-                    crate::_start();
+                    crate::restart_ui();
+                    STATUS_SUCCESS
                 } else {
-                    "Already in fullscreen mode"
+                    let _ = writeln!(self, "Already in fullscreen mode");
+                    STATUS_FAILURE
+                }
+            },
+            _ => {
+                let _ = writeln!(self, "Unknown command '{}'. Type 'help' for available commands.", name);
+                STATUS_NOT_FOUND
+            },
+        }
+    }
+
+    fn window_command(&mut self, args: &[&str]) -> i32 {
+        match args {
+            ["list"] => {
+                let manager = WINDOW_MANAGER.lock();
+                let active = manager.active_window();
+                let lines: Vec<String> = manager
+                    .windows()
+                    .map(|(handle, window)| {
+                        let marker = if Some(handle) == active { '*' } else { ' ' };
+                        format!(
+                            "{:>2}{} {:>2},{:<2} {:>2}x{:<2} {}",
+                            handle.id(), marker, window.x, window.y, window.width, window.height, window.title
+                        )
+                    })
+                    .collect();
+                drop(manager);
+                for line in lines.iter().rev() {
+                    let _ = writeln!(self, "{}", line);
+                }
+                STATUS_SUCCESS
+            },
+            ["open", x, y, width, height, color, title @ ..] => {
+                let geometry = (x.parse(), y.parse(), width.parse(), height.parse());
+                let (x, y, width, height) = match geometry {
+                    (Ok(x), Ok(y), Ok(width), Ok(height)) if width >= 3 && height >= 3 => (x, y, width, height),
+                    _ => {
+                        let _ = writeln!(self, "window: invalid geometry");
+                        return STATUS_FAILURE;
+                    }
+                };
+                let color = match Color::from_name(color) {
+                    Some(color) => color,
+                    None => {
+                        let _ = writeln!(self, "window: unknown color '{}'", color);
+                        return STATUS_FAILURE;
+                    }
+                };
+                let window = Window::new(x, y, width, height, &title.join(" "), color);
+
+                let handle = WINDOW_MANAGER.lock().add_window(window);
+                match handle {
+                    Some(handle) => {
+                        // Keep keyboard focus on the terminal that opened the window.
+                        if let TerminalOutput::Windowed(own) = &self.output {
+                            WINDOW_MANAGER.lock().set_active_window(*own);
+                        }
+                        let _ = writeln!(self, "{}", handle.id());
+                        STATUS_SUCCESS
+                    },
+                    None => {
+                        let _ = writeln!(self, "window: too many windows");
+                        STATUS_FAILURE
+                    },
+                }
+            },
+            ["print", id, x, y, text @ ..] => {
+                match (window_handle(id), x.parse(), y.parse()) {
+                    (Some(window), Ok(x), Ok(y)) => {
+                        window.print_at(x, y, &text.join(" "));
+                        STATUS_SUCCESS
+                    },
+                    _ => {
+                        let _ = writeln!(self, "window: invalid window or position");
+                        STATUS_FAILURE
+                    },
+                }
+            },
+            ["title", id, title @ ..] => match window_handle(id) {
+                Some(handle) => {
+                    handle.set_title(&title.join(" "));
+                    STATUS_SUCCESS
+                },
+                None => {
+                    let _ = writeln!(self, "window: no window {}", id);
+                    STATUS_FAILURE
+                },
+            },
+            ["focus", id] => match window_handle(id) {
+                Some(handle) => {
+                    WINDOW_MANAGER.lock().set_active_window(handle);
+                    STATUS_SUCCESS
+                },
+                None => {
+                    let _ = writeln!(self, "window: no window {}", id);
+                    STATUS_FAILURE
+                },
+            },
+            ["close", id] => {
+                match window_handle(id) {
+                    Some(handle) if crate::terminal::close_window(handle) => STATUS_SUCCESS,
+                    _ => {
+                        let _ = writeln!(self, "window: cannot close window {}", id);
+                        STATUS_FAILURE
+                    },
+                }
+            },
+            _ => {
+                let _ = writeln!(self, "usage: window list");
+                let _ = writeln!(self, "       window close ID");
+                let _ = writeln!(self, "       window focus ID");
+                let _ = writeln!(self, "       window open X Y WIDTH HEIGHT COLOR TITLE...");
+                let _ = writeln!(self, "       window print ID X Y TEXT...");
+                let _ = writeln!(self, "       window title ID TEXT...");
+                STATUS_FAILURE
+            },
+        }
+    }
+
+    fn evaluate_test(&self, args: &[&str]) -> i32 {
+        let result = match args {
+            [] => false,
+            ["!", rest @ ..] => self.evaluate_test(rest) == STATUS_FAILURE,
+            [value] => !value.is_empty(),
+            ["-n", value] => !value.is_empty(),
+            ["-z", value] => value.is_empty(),
+            ["-f", path] => RAMDISK.lock().read(&self.resolve_path(path)).is_some(),
+            ["-d", path] => RAMDISK.lock().is_dir(&self.resolve_path(path)),
+            [left, "=", right] => left == right,
+            [left, "!=", right] => left != right,
+            [left, op, right] => {
+                let (left, right) = match (left.parse::<i64>(), right.parse::<i64>()) {
+                    (Ok(left), Ok(right)) => (left, right),
+                    _ => return STATUS_SYNTAX_ERROR,
+                };
+                match *op {
+                    "-eq" => left == right,
+                    "-ne" => left != right,
+                    "-lt" => left < right,
+                    "-le" => left <= right,
+                    "-gt" => left > right,
+                    "-ge" => left >= right,
+                    _ => return STATUS_SYNTAX_ERROR,
                 }
             },
-            "" => "",
-            _ => "Unknown command. Type 'help' for available commands.",
+            _ => return STATUS_SYNTAX_ERROR,
+        };
+
+        if result { STATUS_SUCCESS } else { STATUS_FAILURE }
+    }
+
+    fn read_input(&mut self, files: &[&str]) -> Result<String, i32> {
+        if files.is_empty() {
+            return Ok(self.stdin.take().unwrap_or_default());
         }
+
+        let mut input = String::new();
+        for file in files {
+            let path = self.resolve_path(file);
+            let ramdisk = RAMDISK.lock();
+            match ramdisk.read(&path) {
+                Some(data) => input.push_str(&String::from_utf8_lossy(data)),
+                None => {
+                    drop(ramdisk);
+                    let _ = writeln!(self, "{}: no such file", file);
+                    return Err(STATUS_FAILURE);
+                }
+            }
         }
+        Ok(input)
+    }
+}
+
+fn window_handle(id: &str) -> Option<WindowHandle> {
+    id.parse().ok().and_then(|id| WINDOW_MANAGER.lock().handle(id))
+}
+
+fn evaluate_expr(args: &[&str]) -> Option<i64> {
+    let (first, rest) = args.split_first()?;
+    let mut value = first.parse::<i64>().ok()?;
+
+    for pair in rest.chunks(2) {
+        let (op, operand) = match pair {
+            [op, operand] => (*op, operand.parse::<i64>().ok()?),
+            _ => return None,
+        };
+        value = match op {
+            "+" => value.checked_add(operand)?,
+            "-" => value.checked_sub(operand)?,
+            "*" => value.checked_mul(operand)?,
+            "/" => value.checked_div(operand)?,
+            "%" => value.checked_rem(operand)?,
+            // Comparisons are spelled as for `test`, since the shell takes
+            // `>` for a redirection.
+            "=" | "-eq" => (value == operand) as i64,
+            "!=" | "-ne" => (value != operand) as i64,
+            "-lt" => (value < operand) as i64,
+            "-le" => (value <= operand) as i64,
+            "-gt" => (value > operand) as i64,
+            "-ge" => (value >= operand) as i64,
+            _ => return None,
+        };
+    }
+
+    Some(value)
 }
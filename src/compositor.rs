@@ -0,0 +1,186 @@
+use crate::vga::{Color, ColorCode, WRITER};
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use crate::sync::Mutex;
+
+pub const SCREEN_WIDTH: usize = 80;
+pub const SCREEN_HEIGHT: usize = 25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub byte: u8,
+    pub color: ColorCode,
+}
+
+impl Cell {
+    pub fn new(byte: u8, foreground: Color) -> Self {
+        Cell {
+            byte,
+            color: ColorCode::new(foreground, Color::Black),
+        }
+    }
+}
+
+// Off-screen character/attribute buffer holding one window's frame and contents.
+pub struct Surface {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+}
+
+impl Surface {
+    pub fn new(width: usize, height: usize) -> Self {
+        Surface {
+            width,
+            height,
+            cells: vec![Cell::new(b' ', Color::White); width * height],
+        }
+    }
+
+    pub fn get(&self, col: usize, row: usize) -> Option<Cell> {
+        if col < self.width && row < self.height {
+            Some(self.cells[row * self.width + col])
+        } else {
+            None
+        }
+    }
+
+    pub fn set(&mut self, col: usize, row: usize, cell: Cell) {
+        if col < self.width && row < self.height {
+            self.cells[row * self.width + col] = cell;
+        }
+    }
+
+    pub fn fill(&mut self, col: usize, row: usize, width: usize, height: usize, cell: Cell) {
+        for row in row..(row + height).min(self.height) {
+            for col in col..(col + width).min(self.width) {
+                self.cells[row * self.width + col] = cell;
+            }
+        }
+    }
+
+    pub fn scroll_up(&mut self, col: usize, row: usize, width: usize, height: usize, blank: Cell) {
+        let right = (col + width).min(self.width);
+        let bottom = (row + height).min(self.height);
+        if col >= right || row >= bottom {
+            return;
+        }
+
+        for line in (row + 1)..bottom {
+            let from = line * self.width;
+            let to = (line - 1) * self.width;
+            self.cells.copy_within(from + col..from + right, to + col);
+        }
+        self.fill(col, bottom - 1, right - col, 1, blank);
+    }
+
+    // Returns a copy resized to the new dimensions, keeping whatever still fits.
+    pub fn resized(&self, width: usize, height: usize) -> Surface {
+        let mut surface = Surface::new(width, height);
+        let len = width.min(self.width);
+        for row in 0..height.min(self.height) {
+            let from = row * self.width;
+            let to = row * width;
+            surface.cells[to..to + len].copy_from_slice(&self.cells[from..from + len]);
+        }
+        surface
+    }
+}
+
+// A surface placed on screen; layers are composited bottom to top.
+pub struct Layer<'a> {
+    pub x: usize,
+    pub y: usize,
+    pub surface: &'a Surface,
+}
+
+pub struct Compositor {
+    // What was last flushed to VGA memory; `None` where the screen is unknown.
+    shadow: [[Option<Cell>; SCREEN_WIDTH]; SCREEN_HEIGHT],
+    dirty: [[bool; SCREEN_WIDTH]; SCREEN_HEIGHT],
+    deferred: usize,
+    pointer: Option<(usize, usize)>,
+}
+
+impl Compositor {
+    pub fn new() -> Self {
+        Compositor {
+            shadow: [[None; SCREEN_WIDTH]; SCREEN_HEIGHT],
+            dirty: [[false; SCREEN_WIDTH]; SCREEN_HEIGHT],
+            deferred: 0,
+            pointer: None,
+        }
+    }
+
+    pub fn mark_dirty(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        for row in self.dirty.iter_mut().take(y + height).skip(y) {
+            for dirty in row.iter_mut().take(x + width).skip(x) {
+                *dirty = true;
+            }
+        }
+    }
+
+    // Forgets what is on screen, for when something else drew over VGA memory.
+    pub fn invalidate(&mut self) {
+        self.shadow = [[None; SCREEN_WIDTH]; SCREEN_HEIGHT];
+        self.mark_dirty(0, 0, SCREEN_WIDTH, SCREEN_HEIGHT);
+    }
+
+    // The mouse pointer is drawn by inverting the attribute of the cell under it.
+    pub fn set_pointer(&mut self, pointer: Option<(usize, usize)>) {
+        for (x, y) in self.pointer.into_iter().chain(pointer) {
+            self.mark_dirty(x, y, 1, 1);
+        }
+        self.pointer = pointer;
+    }
+
+    pub fn defer(&mut self) {
+        self.deferred += 1;
+    }
+
+    pub fn resume(&mut self) {
+        self.deferred = self.deferred.saturating_sub(1);
+    }
+
+    // Blends the layers in z-order and writes only the cells that changed.
+    pub fn flush(&mut self, layers: &[Layer]) {
+        if self.deferred > 0 {
+            return;
+        }
+
+        let mut writer = WRITER.lock();
+        let original_color = writer.color_code;
+
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                if !self.dirty[y][x] {
+                    continue;
+                }
+                self.dirty[y][x] = false;
+
+                let mut cell = layers
+                    .iter()
+                    .rev()
+                    .filter(|layer| x >= layer.x && y >= layer.y)
+                    .find_map(|layer| layer.surface.get(x - layer.x, y - layer.y))
+                    .unwrap_or(Cell::new(b' ', Color::White));
+                if self.pointer == Some((x, y)) {
+                    cell.color = cell.color.inverted();
+                }
+
+                if self.shadow[y][x] != Some(cell) {
+                    writer.color_code = cell.color;
+                    writer.write_char_at(x, y, cell.byte);
+                    self.shadow[y][x] = Some(cell);
+                }
+            }
+        }
+
+        writer.color_code = original_color;
+    }
+}
+
+lazy_static! {
+    pub static ref COMPOSITOR: Mutex<Compositor> = Mutex::new(Compositor::new());
+}
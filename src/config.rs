@@ -1,5 +1,7 @@
-use spin::Mutex;
+use crate::sync::Mutex;
 use lazy_static::lazy_static;
+use alloc::string::String;
+use alloc::vec::Vec;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum UiMode {
@@ -7,8 +9,71 @@ pub enum UiMode {
     FullscreenTerminal,
 }
 
+impl UiMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UiMode::Windowed => "Windowed",
+            UiMode::FullscreenTerminal => "Fullscreen Terminal",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyboardLayout {
+    Us104,
+    Uk105,
+    Dvorak104,
+    Azerty,
+    Jis109,
+}
+
+impl KeyboardLayout {
+    pub const ALL: [KeyboardLayout; 5] = [
+        KeyboardLayout::Us104,
+        KeyboardLayout::Uk105,
+        KeyboardLayout::Dvorak104,
+        KeyboardLayout::Azerty,
+        KeyboardLayout::Jis109,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyboardLayout::Us104 => "us",
+            KeyboardLayout::Uk105 => "uk",
+            KeyboardLayout::Dvorak104 => "dvorak",
+            KeyboardLayout::Azerty => "azerty",
+            KeyboardLayout::Jis109 => "jis",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<KeyboardLayout> {
+        KeyboardLayout::ALL.into_iter().find(|layout| layout.name() == name)
+    }
+}
+
+// A built-in layout, optionally with keys remapped by a keymap table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    pub name: String,
+    pub base: KeyboardLayout,
+    // Key name as `KeyCode` prints it, then its plain, shifted and AltGr
+    // characters.
+    pub overrides: Vec<(String, char, char, Option<char>)>,
+}
+
+impl Keymap {
+    pub fn new(base: KeyboardLayout) -> Self {
+        Keymap {
+            name: String::from(base.name()),
+            base,
+            overrides: Vec::new(),
+        }
+    }
+}
+
 lazy_static! {
     static ref CURRENT_UI_MODE: Mutex<UiMode> = Mutex::new(UiMode::Windowed);
+    static ref CURRENT_KEYMAP: Mutex<Keymap> = Mutex::new(Keymap::new(KeyboardLayout::Us104));
 }
 
 pub fn set_ui_mode(mode: UiMode) {
@@ -17,4 +82,12 @@ pub fn set_ui_mode(mode: UiMode) {
 
 pub fn get_current_ui_mode() -> UiMode {
     *CURRENT_UI_MODE.lock()
-}
\ No newline at end of file
+}
+
+pub fn set_keymap(keymap: Keymap) {
+    *CURRENT_KEYMAP.lock() = keymap;
+}
+
+pub fn with_keymap<R>(f: impl FnOnce(&Keymap) -> R) -> R {
+    f(&CURRENT_KEYMAP.lock())
+}
@@ -0,0 +1,124 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::memory::{USER_SPACE_END, USER_SPACE_START};
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const CLASS_64: u8 = 2;
+const LITTLE_ENDIAN: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 0x3e;
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+// A `PT_LOAD` segment: `file_size` bytes from `offset` in the file, followed
+// by zeroes up to `memory_size`, placed at `address`.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub address: u64,
+    pub memory_size: u64,
+    pub offset: usize,
+    pub file_size: usize,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+pub struct Elf<'a> {
+    pub data: &'a [u8],
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+}
+
+impl Elf<'_> {
+    pub fn segment_data(&self, segment: &Segment) -> &[u8] {
+        &self.data[segment.offset..segment.offset + segment.file_size]
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+// Checks that the file is a static x86_64 executable whose segments lie
+// inside the file and inside user space, and collects what the loader needs.
+pub fn parse(data: &[u8]) -> Result<Elf<'_>, String> {
+    if data.len() < HEADER_SIZE || !data.starts_with(ELF_MAGIC) {
+        return Err(String::from("not an ELF file"));
+    }
+    if data[4] != CLASS_64 || data[5] != LITTLE_ENDIAN {
+        return Err(String::from("not a 64-bit little-endian ELF file"));
+    }
+    if read_u16(data, 16) != TYPE_EXECUTABLE {
+        return Err(String::from("not a static executable"));
+    }
+    if read_u16(data, 18) != MACHINE_X86_64 {
+        return Err(String::from("not an x86_64 executable"));
+    }
+
+    let entry = read_u64(data, 24);
+    let program_headers = read_u64(data, 32) as usize;
+    let header_size = read_u16(data, 54) as usize;
+    let header_count = read_u16(data, 56) as usize;
+    if header_size != PROGRAM_HEADER_SIZE {
+        return Err(format!("unexpected program header size {}", header_size));
+    }
+    let headers_end = header_count
+        .checked_mul(PROGRAM_HEADER_SIZE)
+        .and_then(|size| size.checked_add(program_headers));
+    if headers_end.is_none_or(|end| end > data.len()) {
+        return Err(String::from("program headers outside the file"));
+    }
+
+    let mut segments = Vec::new();
+    for index in 0..header_count {
+        let header = program_headers + index * PROGRAM_HEADER_SIZE;
+        if read_u32(data, header) != PT_LOAD || read_u64(data, header + 40) == 0 {
+            continue;
+        }
+        let flags = read_u32(data, header + 4);
+        let segment = Segment {
+            offset: read_u64(data, header + 8) as usize,
+            address: read_u64(data, header + 16),
+            file_size: read_u64(data, header + 32) as usize,
+            memory_size: read_u64(data, header + 40),
+            writable: flags & PF_W != 0,
+            executable: flags & PF_X != 0,
+        };
+
+        if segment.offset.checked_add(segment.file_size).is_none_or(|end| end > data.len()) {
+            return Err(format!("segment {} outside the file", index));
+        }
+        if segment.file_size as u64 > segment.memory_size {
+            return Err(format!("segment {} is larger in the file than in memory", index));
+        }
+        let in_user_space = segment.address >= USER_SPACE_START
+            && segment.address.checked_add(segment.memory_size).is_some_and(|end| end <= USER_SPACE_END);
+        if !in_user_space {
+            return Err(format!("segment {} outside user space", index));
+        }
+        segments.push(segment);
+    }
+
+    let entry_mapped = segments.iter().any(|segment| {
+        segment.executable && entry >= segment.address && entry < segment.address + segment.memory_size
+    });
+    if !entry_mapped {
+        return Err(String::from("entry point outside the executable segments"));
+    }
+    Ok(Elf { data, entry, segments })
+}
@@ -0,0 +1,73 @@
+use crate::keyboard::KeyEvent;
+use crate::mouse::MouseEvent;
+use crate::sync::{IrqMutex, WaitQueue};
+use crate::window_manager::MAX_WINDOWS;
+use alloc::vec::Vec;
+use crossbeam_queue::ArrayQueue;
+use lazy_static::lazy_static;
+
+const QUEUE_CAPACITY: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Key(KeyEvent),
+    FocusGained,
+    FocusLost,
+    Resized(usize, usize),
+    // Pointer position relative to the window's top-left corner.
+    Mouse(MouseEvent),
+}
+
+lazy_static! {
+    // One queue per window slot. Events carry the generation of the window they
+    // were posted to, so a window reusing the slot never sees its predecessor's.
+    static ref QUEUES: Vec<ArrayQueue<(u32, Event)>> =
+        (0..MAX_WINDOWS).map(|_| ArrayQueue::new(QUEUE_CAPACITY)).collect();
+}
+
+// Threads blocked in `wait`, one queue per window slot.
+static READERS: [WaitQueue; MAX_WINDOWS] = [const { WaitQueue::new() }; MAX_WINDOWS];
+
+// Slots woken since their readers last checked. Waiting checks it under the
+// lock, so a wakeup sent just before a reader blocks isn't lost.
+static WAKEUPS: IrqMutex<[bool; MAX_WINDOWS]> = IrqMutex::new([false; MAX_WINDOWS]);
+
+pub fn post(slot: usize, generation: u32, event: Event) {
+    if let Some(queue) = QUEUES.get(slot) {
+        // A window that stops reading loses its oldest events, not its newest.
+        queue.force_push((generation, event));
+        wake(slot);
+    }
+}
+
+// Wakes threads waiting on the slot, or the next one to wait if there are none.
+pub fn wake(slot: usize) {
+    let mut wakeups = WAKEUPS.lock();
+    if let (Some(woken), Some(readers)) = (wakeups.get_mut(slot), READERS.get(slot)) {
+        *woken = true;
+        readers.wake_all();
+    }
+}
+
+// Blocks until the slot is woken. Returns at once if events are queued, so
+// callers poll, wait and poll again.
+pub fn wait(slot: usize) {
+    let mut wakeups = WAKEUPS.lock();
+    let (Some(woken), Some(queue), Some(readers)) = (wakeups.get_mut(slot), QUEUES.get(slot), READERS.get(slot)) else {
+        return;
+    };
+    if core::mem::take(woken) || !queue.is_empty() {
+        return;
+    }
+    readers.wait(wakeups);
+}
+
+pub fn poll(slot: usize, generation: u32) -> Option<Event> {
+    let queue = QUEUES.get(slot)?;
+    while let Some((posted_to, event)) = queue.pop() {
+        if posted_to == generation {
+            return Some(event);
+        }
+    }
+    None
+}
@@ -0,0 +1,52 @@
+use core::ptr::{addr_of, addr_of_mut};
+use lazy_static::lazy_static;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+// The CPU reads the ring 0 stack from here when an interrupt arrives in user
+// mode; the scheduler points it at the running thread's kernel stack.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    tss: SegmentSelector,
+}
+
+lazy_static! {
+    // User data comes right before user code, the order `sysret` expects.
+    static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        let mut gdt = GlobalDescriptorTable::new();
+        let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code = gdt.add_entry(Descriptor::user_code_segment());
+        let tss = gdt.add_entry(Descriptor::tss_segment(unsafe { &*addr_of!(TSS) }));
+        (gdt, Selectors { kernel_code, kernel_data, user_data, user_code, tss })
+    };
+}
+
+pub fn init() {
+    GDT.0.load();
+    let selectors = &GDT.1;
+    unsafe {
+        CS::set_reg(selectors.kernel_code);
+        SS::set_reg(selectors.kernel_data);
+        DS::set_reg(selectors.kernel_data);
+        ES::set_reg(selectors.kernel_data);
+        load_tss(selectors.tss);
+    }
+}
+
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
+
+pub fn set_kernel_stack(top: VirtAddr) {
+    unsafe { (*addr_of_mut!(TSS)).privilege_stack_table[0] = top };
+}
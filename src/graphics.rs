@@ -1,4 +1,4 @@
-use crate::window_manager::Window;
+use crate::window_manager::WindowHandle;
 use core::f32;
 
 #[derive(Debug, Clone, Copy)]
@@ -135,7 +135,7 @@ impl Renderer3D {
         (screen_x as usize, screen_y as usize)
     }
     
-    fn draw_line(&self, window: &Window, from: (usize, usize), to: (usize, usize)) {
+    fn draw_line(&self, window: &WindowHandle, from: (usize, usize), to: (usize, usize)) {
         let (x0, y0) = from;
         let (x1, y1) = to;
         
@@ -155,7 +155,7 @@ impl Renderer3D {
         }
     }
     
-    pub fn render_object(&self, window: &Window, object: &Object3D) {
+    pub fn render_object(&self, window: &WindowHandle, object: &Object3D) {
         for line in object.lines {
             let from_2d = self.project_point(line.from);
             let to_2d = self.project_point(line.to);
@@ -0,0 +1,49 @@
+use alloc::vec::Vec;
+use crate::ramdisk::{self, RAMDISK};
+
+// Packed by build.rs: an 8 byte magic, then for each file a little-endian u32
+// path length, the absolute path, a u32 data length and the data.
+static INITRD: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initrd.img"));
+const MAGIC: &[u8] = b"INITRD1\0";
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// Copies every file into the ramdisk, creating directories as needed.
+pub fn unpack() {
+    let data = match INITRD.strip_prefix(MAGIC) {
+        Some(data) => data,
+        None => return,
+    };
+    let mut ramdisk = RAMDISK.lock();
+    let mut offset = 0;
+    while offset < data.len() {
+        let path_length = match read_u32(data, offset) {
+            Some(length) => length as usize,
+            None => return,
+        };
+        let path = data.get(offset + 4..offset + 4 + path_length).and_then(|path| core::str::from_utf8(path).ok());
+        offset += 4 + path_length;
+        let data_length = match read_u32(data, offset) {
+            Some(length) => length as usize,
+            None => return,
+        };
+        let contents = data.get(offset + 4..offset + 4 + data_length);
+        offset += 4 + data_length;
+
+        if let (Some(path), Some(contents)) = (path, contents) {
+            let mut dir = ramdisk::parent(path);
+            let mut missing = Vec::new();
+            while dir != "/" && !ramdisk.is_dir(dir) {
+                missing.push(dir);
+                dir = ramdisk::parent(dir);
+            }
+            for dir in missing.into_iter().rev() {
+                ramdisk.mkdir(dir);
+            }
+            ramdisk.write(path, contents);
+        }
+    }
+}
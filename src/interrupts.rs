@@ -1,29 +1,46 @@
 use lazy_static::lazy_static;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::registers::control::Cr2;
+use x86_64::VirtAddr;
 use pic8259::ChainedPics;
-use spin;
+use crate::sync::IrqMutex;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
-pub static PICS: spin::Mutex<ChainedPics> =
-    spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+pub static PICS: IrqMutex<ChainedPics> =
+    IrqMutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Mouse = PIC_2_OFFSET + 4,
 }
 
+// Software interrupt a thread raises to give up the rest of its time slice.
+pub const YIELD_VECTOR: u8 = 0x81;
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         
-        idt[InterruptIndex::Timer as usize]
-            .set_handler_fn(timer_interrupt_handler);
+        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        
+        // These switch stacks, so they can't use the `x86-interrupt` ABI.
+        unsafe {
+            idt[InterruptIndex::Timer as usize]
+                .set_handler_addr(VirtAddr::new(timer_interrupt_handler as *const () as u64));
+            idt[YIELD_VECTOR as usize]
+                .set_handler_addr(VirtAddr::new(yield_interrupt_handler as *const () as u64));
+        }
         idt[InterruptIndex::Keyboard as usize]
             .set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Mouse as usize]
+            .set_handler_fn(mouse_interrupt_handler);
         
         idt
     };
@@ -33,12 +50,102 @@ pub fn init_idt() {
     IDT.load();
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(
-    _stack_frame: InterruptStackFrame)
+// A fault in user mode only ends the process that caused it.
+fn end_user_thread(stack_frame: &InterruptStackFrame) {
+    if stack_frame.code_segment & 3 == 3 {
+        // Coming from ring 3, this thread holds no kernel locks and is on its
+        // own kernel stack. Freeing the process needs the allocator, whose lock
+        // a preempted thread may hold, so let the scheduler run again first.
+        x86_64::instructions::interrupts::enable();
+        crate::process::exit_current();
+    }
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode)
+{
+    end_user_thread(&stack_frame);
+    panic!("EXCEPTION: PAGE FAULT at {:?} ({:?})\n{:#?}", Cr2::read(), error_code, stack_frame);
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64)
 {
+    end_user_thread(&stack_frame);
+    panic!("EXCEPTION: GENERAL PROTECTION FAULT ({:#x})\n{:#?}", error_code, stack_frame);
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(
+    stack_frame: InterruptStackFrame)
+{
+    end_user_thread(&stack_frame);
+    panic!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
+}
+
+// Saves the interrupted thread's registers on its own stack, lets `$switch`
+// pick the stack to continue on and restores the registers found there.
+macro_rules! switching_handler {
+    ($name:ident, $switch:path) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() {
+            core::arch::naked_asm!(
+                "push rax",
+                "push rbx",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                "mov rdi, rsp",
+                "call {switch}",
+                "mov rsp, rax",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rbp",
+                "pop rdi",
+                "pop rsi",
+                "pop rdx",
+                "pop rcx",
+                "pop rbx",
+                "pop rax",
+                "iretq",
+                switch = sym $switch,
+            );
+        }
+    };
+}
+
+switching_handler!(timer_interrupt_handler, timer_switch);
+switching_handler!(yield_interrupt_handler, yield_switch);
+
+extern "C" fn timer_switch(rsp: u64) -> u64 {
+    crate::time::tick();
+    
     unsafe {
         PICS.lock().notify_end_of_interrupt(InterruptIndex::Timer as u8);
     }
+    
+    crate::thread::switch(rsp, true)
+}
+
+extern "C" fn yield_switch(rsp: u64) -> u64 {
+    crate::thread::switch(rsp, false)
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(
@@ -54,4 +161,19 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
     unsafe {
         PICS.lock().notify_end_of_interrupt(InterruptIndex::Keyboard as u8);
     }
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(
+    _stack_frame: InterruptStackFrame)
+{
+    use x86_64::instructions::port::Port;
+    
+    let mut port = Port::new(0x60);
+    let byte: u8 = unsafe { port.read() };
+    
+    crate::mouse::handle_mouse_interrupt(byte);
+    
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(InterruptIndex::Mouse as u8);
+    }
 }
\ No newline at end of file
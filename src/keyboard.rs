@@ -1,50 +1,310 @@
-use spin::Mutex;
+use crate::sync::Mutex;
 use lazy_static::lazy_static;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-use crate::terminal::TERMINAL;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crossbeam_queue::ArrayQueue;
+use spin::Once;
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyState, Keyboard, KeyboardLayout as _, ScancodeSet1};
+use crate::config::{self, KeyboardLayout, Keymap, UiMode};
+use crate::ps2;
+use crate::task::{QueueStream, WakerSlot};
+use crate::events::Event;
+use crate::{terminal, window_manager};
+use crate::window_manager::WindowAction;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+// A key press or release, as delivered to windows and consoles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub pressed: bool,
+    pub modifiers: Modifiers,
+    // The character the key types with the current layout and Shift state.
+    // Ctrl and Alt don't change it; check `modifiers` for chords.
+    pub character: Option<char>,
+}
+
+impl KeyEvent {
+    // A plain typed character, as opposed to part of a Ctrl or Alt chord.
+    pub fn typed_char(&self) -> Option<char> {
+        if !self.pressed || self.modifiers.ctrl || self.modifiers.alt {
+            return None;
+        }
+        self.character
+    }
+}
+
+const SCANCODE_QUEUE_SIZE: usize = 128;
+
+const OEM_102_MAKE: u8 = 0x56;
+const OEM_102_BREAK: u8 = 0xd6;
+
+pub const GERMAN_KEYMAP_PATH: &str = "/etc/keymaps/de.map";
+pub const GERMAN_KEYMAP: &str = include_str!("keymaps/de.map");
 
 lazy_static! {
-    static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = 
+    // Only turns scancodes into key codes; characters come from the configured keymap.
+    static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
         Mutex::new(Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore));
-    static ref ESC_PRESSED: Mutex<bool> = Mutex::new(false);
+    // Right Alt is AltGr, which layouts use for extra characters.
+    static ref LAYOUT_MODIFIERS: Mutex<pc_keyboard::Modifiers> = Mutex::new(pc_keyboard::Modifiers {
+        lshift: false,
+        rshift: false,
+        lctrl: false,
+        rctrl: false,
+        numlock: true,
+        capslock: false,
+        alt_gr: false,
+    });
+    static ref ALT_PRESSED: Mutex<bool> = Mutex::new(false);
+    static ref SCROLL_LOCK: Mutex<bool> = Mutex::new(false);
+    static ref WINDOW_MODE: Mutex<bool> = Mutex::new(false);
+}
+
+// Scancodes waiting for the main loop; created in `init` so the interrupt
+// handler never allocates.
+static SCANCODES: Once<ArrayQueue<u8>> = Once::new();
+static SCANCODE_WAKER: WakerSlot = WakerSlot::new();
+
+pub fn init() {
+    SCANCODES.call_once(|| ArrayQueue::new(SCANCODE_QUEUE_SIZE));
+    sync_leds(&LAYOUT_MODIFIERS.lock());
 }
 
-static mut COMMAND_BUFFER: [char; 64] = ['\0'; 64];
-pub static mut BUFFER_POS: usize = 0;
+fn sync_leds(state: &pc_keyboard::Modifiers) {
+    ps2::set_leds(*SCROLL_LOCK.lock(), state.numlock, state.capslock);
+}
 
+// Only queues the scancode; decoding and everything after it runs in the keyboard task.
 pub fn handle_keyboard_interrupt(scancode: u8) {
-    let mut keyboard = KEYBOARD.lock();
-    
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => {
-                    process_character(character);
-                }
-                DecodedKey::RawKey(_key) => {
-                }
+    if let Some(scancodes) = SCANCODES.get() {
+        let _ = scancodes.push(scancode);
+        SCANCODE_WAKER.wake();
+    }
+}
+
+// Only one task should read scancodes; `process_scancodes` is that task.
+pub fn scancodes() -> QueueStream<u8> {
+    let queue = SCANCODES.call_once(|| ArrayQueue::new(SCANCODE_QUEUE_SIZE));
+    QueueStream::new(queue, &SCANCODE_WAKER)
+}
+
+pub async fn process_scancodes() {
+    let mut scancodes = scancodes();
+    loop {
+        let scancode = scancodes.next().await;
+        handle_scancode(scancode);
+    }
+}
+
+fn decode(scancode: u8) -> Option<KeyEvent> {
+    let (code, pressed) = match scancode {
+        // pc-keyboard's set 1 doesn't know the extra ISO key left of Z, so it
+        // stands in as the otherwise unused HashTilde.
+        OEM_102_MAKE | OEM_102_BREAK => (KeyCode::HashTilde, scancode == OEM_102_MAKE),
+        _ => {
+            let key_event = KEYBOARD.lock().add_byte(scancode).ok()??;
+            (key_event.code, key_event.state == KeyState::Down)
+        },
+    };
+
+    let mut state = LAYOUT_MODIFIERS.lock();
+    match code {
+        KeyCode::ShiftLeft => state.lshift = pressed,
+        KeyCode::ShiftRight => state.rshift = pressed,
+        KeyCode::ControlLeft => state.lctrl = pressed,
+        KeyCode::ControlRight => state.rctrl = pressed,
+        KeyCode::AltLeft => *ALT_PRESSED.lock() = pressed,
+        KeyCode::AltRight => state.alt_gr = pressed,
+        KeyCode::CapsLock if pressed => state.capslock = !state.capslock,
+        KeyCode::NumpadLock if pressed => state.numlock = !state.numlock,
+        KeyCode::ScrollLock if pressed => {
+            let mut scroll_lock = SCROLL_LOCK.lock();
+            *scroll_lock = !*scroll_lock;
+        },
+        _ => {},
+    }
+    if pressed && matches!(code, KeyCode::CapsLock | KeyCode::NumpadLock | KeyCode::ScrollLock) {
+        sync_leds(&state);
+    }
+
+    let modifiers = Modifiers { shift: state.is_shifted(), ctrl: state.is_ctrl(), alt: *ALT_PRESSED.lock() };
+    let character = if pressed {
+        config::with_keymap(|keymap| map_keycode(keymap, code, &state))
+    } else {
+        None
+    };
+    Some(KeyEvent { code, pressed, modifiers, character })
+}
+
+fn map_keycode(keymap: &Keymap, code: KeyCode, state: &pc_keyboard::Modifiers) -> Option<char> {
+    if !keymap.overrides.is_empty() {
+        let name = key_name(code);
+        if let Some((_, plain, shifted, alt_gr)) = keymap.overrides.iter().find(|(key, ..)| *key == name) {
+            if state.alt_gr {
+                return *alt_gr;
             }
+            let shift = if plain.is_alphabetic() { state.is_caps() } else { state.is_shifted() };
+            return Some(if shift { *shifted } else { *plain });
         }
     }
 
-    if scancode == 0x01 {
-        set_esc_pressed(true);
+    let decoded = match keymap.base {
+        KeyboardLayout::Us104 => layouts::Us104Key::map_keycode(code, state, HandleControl::Ignore),
+        KeyboardLayout::Uk105 => layouts::Uk105Key::map_keycode(code, state, HandleControl::Ignore),
+        KeyboardLayout::Dvorak104 => layouts::Dvorak104Key::map_keycode(code, state, HandleControl::Ignore),
+        KeyboardLayout::Azerty => layouts::Azerty::map_keycode(code, state, HandleControl::Ignore),
+        KeyboardLayout::Jis109 => layouts::Jis109Key::map_keycode(code, state, HandleControl::Ignore),
+    };
+    match decoded {
+        DecodedKey::Unicode(character) => Some(character),
+        DecodedKey::RawKey(_) => None,
+    }
+}
+
+// How keymap tables name a key.
+fn key_name(code: KeyCode) -> String {
+    match code {
+        KeyCode::HashTilde => String::from("Oem102"),
+        code => format!("{:?}", code),
+    }
+}
+
+// Reads a keymap table: an optional `base LAYOUT` line, then one line per key
+// with the key name, its character, its shifted character and its AltGr
+// character. Letters default to their upper case when shifted, and keys
+// without an AltGr character type nothing with AltGr. Lines starting with `#`
+// are comments.
+pub fn parse_keymap(name: &str, source: &str) -> Result<Keymap, String> {
+    let mut keymap = Keymap::new(KeyboardLayout::Us104);
+    keymap.name = String::from(name);
+
+    for (number, line) in source.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [] => {},
+            [first, ..] if first.starts_with('#') => {},
+            ["base", layout] => {
+                keymap.base = KeyboardLayout::from_name(layout)
+                    .ok_or_else(|| format!("line {}: unknown layout '{}'", number + 1, layout))?;
+            },
+            [key, plain] | [key, plain, _] | [key, plain, _, _] => {
+                let plain = single_char(plain);
+                let shifted = match fields.get(2) {
+                    Some(shifted) => single_char(shifted),
+                    None => plain.and_then(|plain| plain.to_uppercase().next()),
+                };
+                let alt_gr = match fields.get(3) {
+                    Some(alt_gr) => single_char(alt_gr).map(Some),
+                    None => Some(None),
+                };
+                match (plain, shifted, alt_gr) {
+                    (Some(plain), Some(shifted), Some(alt_gr)) => {
+                        keymap.overrides.push((String::from(*key), plain, shifted, alt_gr))
+                    },
+                    _ => return Err(format!("line {}: expected single characters", number + 1)),
+                }
+            },
+            _ => return Err(format!("line {}: expected KEY CHAR [SHIFTED [ALTGR]]", number + 1)),
+        }
     }
+    Ok(keymap)
+}
+
+fn single_char(field: &str) -> Option<char> {
+    let mut chars = field.chars();
+    let character = chars.next()?;
+    chars.next().is_none().then_some(character)
 }
 
-fn process_character(c: char) {
-    unsafe {
-        let mut terminal_lock = TERMINAL.lock();
-        if let Some(terminal) = terminal_lock.as_mut() {
-            terminal.handle_input(c, &mut COMMAND_BUFFER, &mut BUFFER_POS);
+fn handle_scancode(scancode: u8) {
+    // Replies to LED updates arrive on the same port as scancodes.
+    if scancode == ps2::DEVICE_ACK || scancode == ps2::DEVICE_RESEND {
+        return;
+    }
+    let key = match decode(scancode) {
+        Some(key) => key,
+        None => return,
+    };
+    let windowed = config::get_current_ui_mode() == UiMode::Windowed;
+
+    if key.pressed && matches!(key.code, KeyCode::WindowsLeft | KeyCode::WindowsRight) {
+        let enabled = !*WINDOW_MODE.lock();
+        set_window_mode(enabled);
+        return;
+    }
+
+    if *WINDOW_MODE.lock() {
+        if key.pressed {
+            handle_window_key(key);
+        }
+        return;
+    }
+
+    if key.pressed && key.modifiers.alt {
+        if key.code == KeyCode::Tab && windowed {
+            window_manager::cycle_focus();
+            return;
         }
+        if let Some(console) = console_for_key(key.code) {
+            terminal::switch_console(console);
+            return;
+        }
+    }
+
+    // In windowed mode keys go to the focused window's event queue and
+    // whoever owns that window reads them from there.
+    if windowed {
+        window_manager::post_key(Event::Key(key));
+        window_manager::dispatch_events();
+    } else {
+        terminal::handle_key(key);
     }
 }
 
-pub fn set_esc_pressed(pressed: bool) {
-    *ESC_PRESSED.lock() = pressed;
+fn set_window_mode(enabled: bool) {
+    let enabled = enabled && config::get_current_ui_mode() == UiMode::Windowed;
+    *WINDOW_MODE.lock() = enabled;
+    window_manager::set_grabbed(enabled);
 }
 
-pub fn is_esc_pressed() -> Option<bool> {
-    Some(*ESC_PRESSED.lock())
-}
\ No newline at end of file
+// In window mode arrows move the focused window, Shift+arrows resize it, `m`
+// toggles maximise, `n` minimises, `c` closes and Tab cycles focus.
+fn handle_window_key(key: KeyEvent) {
+    let step = if key.modifiers.shift { WindowAction::Resize } else { WindowAction::Move };
+    let action = match (key.code, key.character) {
+        (KeyCode::ArrowLeft, _) => step(-1, 0),
+        (KeyCode::ArrowRight, _) => step(1, 0),
+        (KeyCode::ArrowUp, _) => step(0, -1),
+        (KeyCode::ArrowDown, _) => step(0, 1),
+        (KeyCode::Tab, _) => WindowAction::CycleFocus,
+        (KeyCode::Enter | KeyCode::Escape, _) => {
+            set_window_mode(false);
+            return;
+        },
+        (_, Some('m')) => WindowAction::Maximize,
+        (_, Some('n')) => WindowAction::Minimize,
+        (_, Some('c')) => WindowAction::Close,
+        _ => return,
+    };
+    window_manager::apply_to_active(action);
+}
+
+fn console_for_key(code: KeyCode) -> Option<usize> {
+    match code {
+        KeyCode::F1 => Some(0),
+        KeyCode::F2 => Some(1),
+        KeyCode::F3 => Some(2),
+        KeyCode::F4 => Some(3),
+        KeyCode::F5 => Some(4),
+        KeyCode::F6 => Some(5),
+        _ => None,
+    }
+}
@@ -2,131 +2,178 @@
 #![no_main]
 #![feature(abi_x86_interrupt)]
 
+extern crate alloc;
+
+use bootloader::BootInfo;
 use core::panic::PanicInfo;
-use core::fmt::{self, Write};
+use x86_64::VirtAddr;
 mod vga;
 mod window_manager;
-
-struct ByteWriter<'a> {
-    buf: &'a mut [u8],
-    cursor: usize,
-}
-
-impl<'a> ByteWriter<'a> {
-    fn new(buf: &'a mut [u8]) -> Self {
-        ByteWriter { buf, cursor: 0 }
-    }
-}
-
-impl<'a> fmt::Write for ByteWriter<'a> {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        let bytes = s.as_bytes();
-        let len = bytes.len().min(self.buf.len() - self.cursor);
-        self.buf[self.cursor..self.cursor + len].copy_from_slice(&bytes[..len]);
-        self.cursor += len;
-        Ok(())
-    }
-}
+mod widgets;
+mod sysmon;
+mod compositor;
+mod events;
 mod interrupts;
 mod keyboard;
+mod mouse;
+mod ps2;
 mod command;
 mod terminal;
 mod config;
 mod graphics;
+mod memory;
+mod allocator;
+mod ramdisk;
+mod shell;
+mod script;
+mod time;
+mod task;
+mod thread;
+mod sync;
+mod gdt;
+mod process;
+mod syscall;
+mod elf;
+mod initrd;
 
 use vga::Color;
-use window_manager::{Window, WINDOW_MANAGER};
+use window_manager::{Window, WindowHandle, WINDOW_MANAGER};
 use config::UiMode;
+use events::Event;
 use graphics::{Renderer3D, create_cube};
+use pc_keyboard::KeyCode;
+use task::{Executor, Task};
+
+const DEMO_FRAME_TICKS: u64 = 3;
 
-pub fn launch_3d_demo() -> Option<usize> {
+// The demo runs as its own task, so the shell stays usable while it runs
+// and it reads ESC from its own window's event queue.
+pub fn launch_3d_demo() -> Result<usize, &'static str> {
     let window = Window::new(15, 4, 50, 20, "3D Graphics Demo", Color::LightBlue);
     
     let mut manager = WINDOW_MANAGER.lock();
-    let window_id = manager.add_window(window)?;
-    manager.set_active_window(window_id);
-    manager.draw_all();
-    
-    let window = manager.get_window(window_id)?;
+    let previous_window = manager.active_window();
+    let window = manager.add_window(window).ok_or("too many windows")?;
     drop(manager);
     
+    window.print_at(1, 18, "Press ESC to close the demo...");
+    // Rendering is slow enough to be worth preempting, so the demo gets its own thread.
+    let spawned = thread::spawn("3d-demo", thread::Priority::Low, move || run_3d_demo(window, previous_window));
+    if spawned.is_none() {
+        let mut manager = WINDOW_MANAGER.lock();
+        manager.remove_window(window);
+        if let Some(previous_window) = previous_window {
+            manager.set_active_window(previous_window);
+        }
+        return Err("too many threads");
+    }
+    Ok(window.id())
+}
+
+fn run_3d_demo(window: WindowHandle, previous_window: Option<WindowHandle>) {
     let mut renderer = Renderer3D::new();
     let cube = create_cube();
-    
-    let mut frame_counter = 0;
     let rotation_speed = 0.05;
     
-    window.print_at(1, 18, "Press ESC to close the demo...");
-
-    loop {
-        if let Some(true) = keyboard::is_esc_pressed() {
-            break;
-        }
-        
-        for y in 2..18 {
-            for x in 2..48 {
-                window.print_at(x, y, " ");
+    // The window is gone if it was closed from its title bar.
+    let mut frame_counter = 0;
+    while window.size().is_some() {
+        let mut escape = false;
+        while let Some(event) = window.poll_event() {
+            if let Event::Key(key) = event {
+                escape |= key.pressed && key.code == KeyCode::Escape;
             }
         }
-        
-        renderer.rotate(rotation_speed, rotation_speed * 1.5, rotation_speed * 0.7);
-        
-        renderer.render_object(&window, &cube);
-        
-        for _ in 0..300000 {
-            core::hint::spin_loop();
+        if escape {
+            break;
         }
         
+        renderer.rotate(rotation_speed, rotation_speed * 1.5, rotation_speed * 0.7);
         frame_counter += 1;
-        let mut frame_str = [0u8; 16];
-        write!(ByteWriter::new(&mut frame_str), "Frame: {}", frame_counter).unwrap();
-        window.print_at(32, 1, core::str::from_utf8(&frame_str).unwrap());
+        
+        window_manager::batch(|| {
+            for y in 2..18 {
+                for x in 2..48 {
+                    window.print_at(x, y, " ");
+                }
+            }
+            
+            renderer.render_object(&window, &cube);
+            window.set_title(&alloc::format!("3D Graphics Demo - Frame {}", frame_counter));
+        });
+        thread::sleep(DEMO_FRAME_TICKS);
     }
     
-    Some(window_id)
+    let mut manager = WINDOW_MANAGER.lock();
+    manager.remove_window(window);
+    if let Some(previous_window) = previous_window {
+        manager.set_active_window(previous_window);
+    }
+    drop(manager);
+    // Focus events belong to whoever owns the windows, on the main thread.
+    task::spawn(async { window_manager::dispatch_events() });
 }
 
 #[no_mangle]
-pub extern "C" fn _start() -> ! {
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_frames(frame_allocator, phys_mem_offset);
+    vga::init();
+    ramdisk::init();
+    initrd::unpack();
+    
+    gdt::init();
+    syscall::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
+    time::init();
+    thread::init();
+    ps2::init();
+    keyboard::init();
+    mouse::init();
     x86_64::instructions::interrupts::enable();
     
+    start_ui();
+    run();
+}
+
+// Rebuilds the UI for the current mode. It runs as a separate task because
+// the calling command's terminal is still running.
+pub fn restart_ui() {
+    task::spawn(async { start_ui() });
+}
+
+// Interrupt handlers only queue input and wake the tasks that process it.
+fn run() -> ! {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(keyboard::process_scancodes()));
+    executor.spawn(Task::new(mouse::process_events()));
+    executor.spawn(Task::new(window_manager::run_taskbar_clock()));
+    executor.run()
+}
+
+pub fn start_ui() {
     match config::get_current_ui_mode() {
         UiMode::Windowed => {
             vga::WRITER.lock().clear_screen();
             
-            let window1 = Window::new(5, 3, 30, 10, "Main Window", Color::Cyan);
-            let window2 = Window::new(40, 5, 35, 8, "System Info", Color::Green);
-            let window3 = Window::new(20, 15, 40, 6, "Terminal", Color::White);
-            
+            window_manager::reset();
             let mut manager = WINDOW_MANAGER.lock();
-            let id1 = manager.add_window(window1).unwrap();
-            let id2 = manager.add_window(window2).unwrap();
-            let id3 = manager.add_window(window3).unwrap();
-            
-            manager.set_active_window(id3);
+            let terminal_window = manager
+                .add_window(Window::new(20, 15, 40, 6, "Terminal", Color::White))
+                .unwrap();
             manager.draw_all();
-            
-            let window1 = manager.get_window(id1).unwrap();
-            window1.print_at(1, 3, "Window Manager Demo");
-            
-            let window2 = manager.get_window(id2).unwrap();
-            window2.print_at(1, 1, "CPU: x86_64");
-            window2.print_at(1, 2, "Memory: 64MB");
-            window2.print_at(1, 3, "Status: Running");
-            
-            let terminal_window = manager.get_window(id3).unwrap();
             drop(manager);
             
-            terminal::init_terminal_windowed(terminal_window);
+            terminal::init_terminal_windowed(terminal_window, Some(script::BOOT_SCRIPT_PATH));
         },
         UiMode::FullscreenTerminal => {
             terminal::init_terminal_fullscreen();
         }
     }
-    
-    loop {}
 }
 
 #[panic_handler]
@@ -0,0 +1,311 @@
+use alloc::vec::Vec;
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicBool, Ordering};
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use spin::Once;
+use x86_64::{
+    registers::control::Cr3,
+    registers::model_specific::{Efer, EferFlags},
+    structures::paging::{
+        mapper::TranslateResult, FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame,
+        Size4KiB, Translate,
+    },
+    PhysAddr, VirtAddr,
+};
+use crate::sync::Mutex;
+
+const PAGE_SIZE: u64 = 4096;
+
+// User programs live in the second level 4 entry, which the kernel leaves
+// empty; every address space shares the kernel's other entries.
+const USER_LEVEL_4_INDEX: usize = 1;
+pub const USER_SPACE_START: u64 = (USER_LEVEL_4_INDEX as u64) << 39;
+pub const USER_SPACE_END: u64 = (USER_LEVEL_4_INDEX as u64 + 1) << 39;
+
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    let level_4_table = active_level_4_table(physical_memory_offset);
+    OffsetPageTable::new(level_4_table, physical_memory_offset)
+}
+
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let phys = level_4_table_frame.start_address();
+    let virt = physical_memory_offset + phys.as_u64();
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+
+    &mut *page_table_ptr
+}
+
+pub struct BootInfoFrameAllocator {
+    memory_map: &'static MemoryMap,
+    next: usize,
+}
+
+impl BootInfoFrameAllocator {
+    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        BootInfoFrameAllocator {
+            memory_map,
+            next: 0,
+        }
+    }
+
+    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+        let regions = self.memory_map.iter();
+        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
+        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
+        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
+        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.usable_frames().nth(self.next);
+        self.next += 1;
+        frame
+    }
+}
+
+// Frames handed out after boot, with freed frames reused before new ones.
+struct Frames {
+    boot: BootInfoFrameAllocator,
+    free: Vec<PhysFrame>,
+}
+
+unsafe impl FrameAllocator<Size4KiB> for Frames {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.free.pop().or_else(|| self.boot.allocate_frame())
+    }
+}
+
+static FRAMES: Once<Mutex<Frames>> = Once::new();
+static PHYSICAL_MEMORY_OFFSET: Once<VirtAddr> = Once::new();
+static KERNEL_LEVEL_4_TABLE: Once<PhysFrame> = Once::new();
+static NO_EXECUTE_SUPPORTED: AtomicBool = AtomicBool::new(false);
+
+// Takes over the frame allocator once the heap exists, for address spaces.
+pub fn init_frames(frame_allocator: BootInfoFrameAllocator, physical_memory_offset: VirtAddr) {
+    PHYSICAL_MEMORY_OFFSET.call_once(|| physical_memory_offset);
+    KERNEL_LEVEL_4_TABLE.call_once(|| Cr3::read().0);
+    FRAMES.call_once(|| Mutex::new(Frames { boot: frame_allocator, free: Vec::new() }));
+    // User data and stacks are mapped NO_EXECUTE where the CPU has it; setting
+    // the bit without NXE enabled would make every such page fault.
+    if no_execute_supported() {
+        unsafe { Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE)) };
+        NO_EXECUTE_SUPPORTED.store(true, Ordering::Relaxed);
+    }
+}
+
+// CPUID leaf 0x8000_0001 reports NX in bit 20 of EDX.
+fn no_execute_supported() -> bool {
+    let highest_extended = __cpuid(0x8000_0000).eax;
+    highest_extended >= 0x8000_0001 && __cpuid(0x8000_0001).edx & (1 << 20) != 0
+}
+
+pub fn kernel_level_4_table() -> PhysFrame {
+    *KERNEL_LEVEL_4_TABLE.get().expect("memory not initialised")
+}
+
+fn frame_table(frame: PhysFrame) -> *mut PageTable {
+    let offset = *PHYSICAL_MEMORY_OFFSET.get().expect("memory not initialised");
+    (offset + frame.start_address().as_u64()).as_mut_ptr()
+}
+
+// Whether the range lies in user space and is mapped for user mode in the
+// active page tables, so the kernel can access it on a process's behalf
+// without faulting.
+pub fn user_range_accessible(start: u64, size: u64, writable: bool) -> bool {
+    let end = match start.checked_add(size) {
+        Some(end) => end,
+        None => return false,
+    };
+    if start < USER_SPACE_START || end > USER_SPACE_END {
+        return false;
+    }
+    if size == 0 {
+        return true;
+    }
+
+    if PHYSICAL_MEMORY_OFFSET.get().is_none() {
+        return false;
+    }
+    let mapper = mapper(Cr3::read().0);
+    let mut required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if writable {
+        required |= PageTableFlags::WRITABLE;
+    }
+    let pages = Page::<Size4KiB>::range_inclusive(
+        Page::containing_address(VirtAddr::new(start)),
+        Page::containing_address(VirtAddr::new(end - 1)),
+    );
+    pages.into_iter().all(|page| match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped { flags, .. } => flags.contains(required),
+        _ => false,
+    })
+}
+
+fn mapper<'a>(level_4_frame: PhysFrame) -> OffsetPageTable<'a> {
+    let offset = *PHYSICAL_MEMORY_OFFSET.get().expect("memory not initialised");
+    unsafe { OffsetPageTable::new(&mut *frame_table(level_4_frame), offset) }
+}
+
+// Maps zeroed, user-accessible pages covering `size` bytes from `start`.
+fn map_user(level_4_frame: PhysFrame, start: VirtAddr, size: u64, writable: bool, executable: bool) -> Option<()> {
+    if size == 0 || start.as_u64() < USER_SPACE_START || start.as_u64().checked_add(size)? > USER_SPACE_END {
+        return None;
+    }
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if writable {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if !executable && NO_EXECUTE_SUPPORTED.load(Ordering::Relaxed) {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+
+    let pages = Page::<Size4KiB>::range_inclusive(
+        Page::containing_address(start),
+        Page::containing_address(start + size - 1u64),
+    );
+    let mut frames = FRAMES.get()?.lock();
+    let mut mapper = mapper(level_4_frame);
+    for page in pages {
+        // Pages shared with an earlier mapping keep its contents but become
+        // writable or executable if either mapping needs it.
+        if let TranslateResult::Mapped { flags: existing, .. } = mapper.translate(page.start_address()) {
+            let mut merged = existing;
+            if writable {
+                merged |= PageTableFlags::WRITABLE;
+            }
+            if executable {
+                merged.remove(PageTableFlags::NO_EXECUTE);
+            }
+            if merged != existing {
+                unsafe { mapper.update_flags(page, merged).ok()?.flush() };
+            }
+            continue;
+        }
+        let frame = frames.allocate_frame()?;
+        unsafe {
+            core::ptr::write_bytes(frame_table(frame) as *mut u8, 0, PAGE_SIZE as usize);
+            match mapper.map_to_with_table_flags(page, frame, flags, table_flags, &mut *frames) {
+                Ok(flush) => flush.flush(),
+                Err(_) => return None,
+            }
+        }
+    }
+    Some(())
+}
+
+// Unmaps whatever is mapped in the range and frees the pages.
+fn unmap_user(level_4_frame: PhysFrame, start: VirtAddr, size: u64) {
+    let end = match start.as_u64().checked_add(size) {
+        Some(end) if size > 0 && start.as_u64() >= USER_SPACE_START && end <= USER_SPACE_END => end,
+        _ => return,
+    };
+    let pages = Page::<Size4KiB>::range_inclusive(
+        Page::containing_address(start),
+        Page::containing_address(VirtAddr::new(end - 1)),
+    );
+    let mut frames = match FRAMES.get() {
+        Some(frames) => frames.lock(),
+        None => return,
+    };
+    let mut mapper = mapper(level_4_frame);
+    for page in pages {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            frames.free.push(frame);
+        }
+    }
+}
+
+// Maps writable user memory into the running process. The range must be
+// unused; if mapping it fails partway, the pages already mapped are freed.
+pub fn map_active_user(start: VirtAddr, size: u64) -> Option<()> {
+    let level_4_frame = Cr3::read().0;
+    let mapped = map_user(level_4_frame, start, size, true, false);
+    if mapped.is_none() {
+        unmap_user(level_4_frame, start, size);
+    }
+    mapped
+}
+
+// A set of page tables for one process: the kernel's mappings plus its own
+// user pages, which are freed with it.
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+}
+
+impl AddressSpace {
+    pub fn new() -> Option<Self> {
+        let kernel_table = unsafe { &*frame_table(kernel_level_4_table()) };
+        if !kernel_table[USER_LEVEL_4_INDEX].is_unused() {
+            return None;
+        }
+        let level_4_frame = FRAMES.get()?.lock().allocate_frame()?;
+        let table = unsafe { &mut *frame_table(level_4_frame) };
+        table.clone_from(kernel_table);
+        Some(AddressSpace { level_4_frame })
+    }
+
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    fn mapper(&mut self) -> OffsetPageTable<'_> {
+        mapper(self.level_4_frame)
+    }
+
+    pub fn map(&mut self, start: VirtAddr, size: u64, writable: bool, executable: bool) -> Option<()> {
+        map_user(self.level_4_frame, start, size, writable, executable)
+    }
+
+    // Copies `data` into already mapped pages, whatever their protection.
+    pub fn write(&mut self, start: VirtAddr, data: &[u8]) -> Option<()> {
+        let offset = *PHYSICAL_MEMORY_OFFSET.get()?;
+        let mapper = self.mapper();
+        let mut written = 0;
+        while written < data.len() {
+            let address = start + written as u64;
+            let physical = mapper.translate_addr(address)?;
+            let chunk = ((PAGE_SIZE - address.as_u64() % PAGE_SIZE) as usize).min(data.len() - written);
+            unsafe {
+                let target: *mut u8 = (offset + physical.as_u64()).as_mut_ptr();
+                core::ptr::copy_nonoverlapping(data[written..].as_ptr(), target, chunk);
+            }
+            written += chunk;
+        }
+        Some(())
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        let mut frames = match FRAMES.get() {
+            Some(frames) => frames.lock(),
+            None => return,
+        };
+        let table = unsafe { &mut *frame_table(self.level_4_frame) };
+        if let Ok(frame) = table[USER_LEVEL_4_INDEX].frame() {
+            free_table(&mut frames.free, frame, 3);
+        }
+        frames.free.push(self.level_4_frame);
+    }
+}
+
+// Frees a user page table along with every table and page below it.
+fn free_table(free: &mut Vec<PhysFrame>, frame: PhysFrame, level: usize) {
+    let table = unsafe { &*frame_table(frame) };
+    for entry in table.iter() {
+        if let Ok(child) = entry.frame() {
+            if level > 1 {
+                free_table(free, child, level - 1);
+            } else {
+                free.push(child);
+            }
+        }
+    }
+    free.push(frame);
+}
@@ -0,0 +1,182 @@
+use crate::interrupts::PICS;
+use crate::ps2::{self, read_data, write_command, write_data};
+use crate::task::{QueueStream, WakerSlot};
+use crossbeam_queue::ArrayQueue;
+use lazy_static::lazy_static;
+use crate::sync::IrqMutex;
+use spin::Once;
+
+const CONTROLLER_ENABLE_AUX: u8 = 0xa8;
+const CONTROLLER_WRITE_AUX: u8 = 0xd4;
+
+const MOUSE_SET_DEFAULTS: u8 = 0xf6;
+const MOUSE_ENABLE_STREAMING: u8 = 0xf4;
+const MOUSE_SET_SAMPLE_RATE: u8 = 0xf3;
+const MOUSE_GET_ID: u8 = 0xf2;
+const INTELLIMOUSE_ID: u8 = 3;
+
+const EVENT_QUEUE_SIZE: usize = 64;
+
+// Movement units per text cell, so the pointer moves at a usable speed.
+const UNITS_PER_COLUMN: isize = 8;
+const UNITS_PER_ROW: isize = 16;
+const SCREEN_WIDTH: isize = 80;
+const SCREEN_HEIGHT: isize = 25;
+
+pub const BUTTON_LEFT: u8 = 0x01;
+pub const BUTTON_RIGHT: u8 = 0x02;
+pub const BUTTON_MIDDLE: u8 = 0x04;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MouseEvent {
+    pub x: usize,
+    pub y: usize,
+    pub buttons: u8,
+    pub previous_buttons: u8,
+    pub wheel: i8,
+}
+
+impl MouseEvent {
+    pub fn pressed(&self, button: u8) -> bool {
+        self.buttons & button != 0 && self.previous_buttons & button == 0
+    }
+
+    pub fn released(&self, button: u8) -> bool {
+        self.buttons & button == 0 && self.previous_buttons & button != 0
+    }
+}
+
+struct Mouse {
+    packet: [u8; 4],
+    index: usize,
+    packet_size: usize,
+    x: isize,
+    y: isize,
+    buttons: u8,
+}
+
+impl Mouse {
+    fn new() -> Self {
+        Mouse {
+            packet: [0; 4],
+            index: 0,
+            packet_size: 3,
+            x: SCREEN_WIDTH * UNITS_PER_COLUMN / 2,
+            y: SCREEN_HEIGHT * UNITS_PER_ROW / 2,
+            buttons: 0,
+        }
+    }
+
+    fn add_byte(&mut self, byte: u8) -> Option<MouseEvent> {
+        // Bit 3 of the first byte is always set; use it to resynchronise.
+        if self.index == 0 && byte & 0x08 == 0 {
+            return None;
+        }
+
+        self.packet[self.index] = byte;
+        self.index += 1;
+        if self.index < self.packet_size {
+            return None;
+        }
+        self.index = 0;
+
+        let flags = self.packet[0];
+        if flags & 0xc0 != 0 {
+            return None;
+        }
+
+        let dx = self.packet[1] as isize - if flags & 0x10 != 0 { 0x100 } else { 0 };
+        let dy = self.packet[2] as isize - if flags & 0x20 != 0 { 0x100 } else { 0 };
+        let wheel = if self.packet_size == 4 {
+            // The low nibble holds a signed 4-bit scroll delta.
+            ((self.packet[3] << 4) as i8) >> 4
+        } else {
+            0
+        };
+
+        self.x = (self.x + dx).clamp(0, SCREEN_WIDTH * UNITS_PER_COLUMN - 1);
+        self.y = (self.y - dy).clamp(0, SCREEN_HEIGHT * UNITS_PER_ROW - 1);
+
+        let previous_buttons = self.buttons;
+        self.buttons = flags & (BUTTON_LEFT | BUTTON_RIGHT | BUTTON_MIDDLE);
+
+        Some(MouseEvent {
+            x: (self.x / UNITS_PER_COLUMN) as usize,
+            y: (self.y / UNITS_PER_ROW) as usize,
+            buttons: self.buttons,
+            previous_buttons,
+            wheel,
+        })
+    }
+}
+
+lazy_static! {
+    static ref MOUSE: IrqMutex<Mouse> = IrqMutex::new(Mouse::new());
+}
+
+// Decoded packets waiting for the main loop; created in `init` so the
+// interrupt handler never allocates.
+static EVENTS: Once<ArrayQueue<MouseEvent>> = Once::new();
+static EVENT_WAKER: WakerSlot = WakerSlot::new();
+
+fn mouse_command(byte: u8) -> bool {
+    write_command(CONTROLLER_WRITE_AUX);
+    write_data(byte);
+    read_data() == Some(ps2::DEVICE_ACK)
+}
+
+fn set_sample_rate(rate: u8) -> bool {
+    mouse_command(MOUSE_SET_SAMPLE_RATE) && mouse_command(rate)
+}
+
+// Must run with interrupts disabled so the handlers don't swallow the replies.
+pub fn init() {
+    EVENTS.call_once(|| ArrayQueue::new(EVENT_QUEUE_SIZE));
+    write_command(CONTROLLER_ENABLE_AUX);
+
+    let config = ps2::read_config();
+    ps2::write_config((config | ps2::CONFIG_AUX_INTERRUPT) & !ps2::CONFIG_AUX_CLOCK_DISABLED);
+
+    if !mouse_command(MOUSE_SET_DEFAULTS) {
+        return;
+    }
+
+    // The IntelliMouse knock sequence switches wheel mice to 4-byte packets.
+    let intellimouse = set_sample_rate(200)
+        && set_sample_rate(100)
+        && set_sample_rate(80)
+        && mouse_command(MOUSE_GET_ID)
+        && read_data() == Some(INTELLIMOUSE_ID);
+    if intellimouse {
+        MOUSE.lock().packet_size = 4;
+    }
+
+    if !mouse_command(MOUSE_ENABLE_STREAMING) {
+        return;
+    }
+
+    unsafe {
+        let mut pics = PICS.lock();
+        let [primary, secondary] = pics.read_masks();
+        // Unmask the cascade line (IRQ2) and the mouse (IRQ12).
+        pics.write_masks(primary & !0x04, secondary & !0x10);
+    }
+}
+
+pub fn handle_mouse_interrupt(byte: u8) {
+    let event = MOUSE.lock().add_byte(byte);
+    if let (Some(event), Some(events)) = (event, EVENTS.get()) {
+        // Drop new packets when the queue is full so button changes stay in order.
+        let _ = events.push(event);
+        EVENT_WAKER.wake();
+    }
+}
+
+pub async fn process_events() {
+    let queue = EVENTS.call_once(|| ArrayQueue::new(EVENT_QUEUE_SIZE));
+    let mut events = QueueStream::new(queue, &EVENT_WAKER);
+    loop {
+        let event = events.next().await;
+        crate::window_manager::handle_mouse(event);
+    }
+}
@@ -0,0 +1,212 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use x86_64::VirtAddr;
+use crate::elf;
+use crate::memory::{AddressSpace, USER_SPACE_START};
+use crate::sync::Mutex;
+use crate::terminal::TerminalBinding;
+use crate::thread::{self, ThreadId};
+use crate::window_manager::{WindowHandle, WINDOW_MANAGER};
+
+const USER_STACK_TOP: u64 = USER_SPACE_START + 0x4000_0000;
+const USER_STACK_SIZE: u64 = 4 * 4096;
+// Memory a process maps without asking for an address goes from here up.
+const USER_MAP_START: u64 = USER_SPACE_START + 0x1000_0000;
+const USER_MAP_END: u64 = USER_STACK_TOP - USER_STACK_SIZE;
+// Anonymous memory one process may map, so it can't take every free frame.
+const MAX_MAPPED: u64 = 4 * 1024 * 1024;
+const MAX_ARGUMENTS_SIZE: usize = 4096;
+
+// Kernel-side state of a running process. Its address space and kernel
+// stack belong to its thread.
+pub struct Process {
+    id: ThreadId,
+    // The terminal that started the process, which shows what it writes.
+    pub console: Option<TerminalBinding>,
+    pub windows: Vec<WindowHandle>,
+    next_map: u64,
+    mapped: u64,
+    in_syscall: bool,
+    killed: bool,
+}
+
+lazy_static! {
+    static ref PROCESSES: Mutex<Vec<Process>> = Mutex::new(Vec::new());
+}
+
+// Loads an ELF executable into a fresh address space and starts it in ring 3.
+// The stack starts with argc, the argv and envp pointer arrays and an empty
+// auxiliary vector, as on System V; argc, argv and envp are also passed in
+// rdi, rsi and rdx.
+pub fn exec(
+    name: &str,
+    image: &[u8],
+    args: &[&str],
+    env: &[String],
+    console: Option<TerminalBinding>,
+) -> Result<ThreadId, String> {
+    let elf = elf::parse(image)?;
+    let mut address_space = AddressSpace::new().ok_or("no address space available")?;
+    for segment in &elf.segments {
+        let start = VirtAddr::new(segment.address);
+        address_space
+            .map(start, segment.memory_size, segment.writable, segment.executable)
+            .and_then(|_| address_space.write(start, elf.segment_data(segment)))
+            .ok_or("out of memory")?;
+    }
+
+    let stack_bottom = VirtAddr::new(USER_STACK_TOP - USER_STACK_SIZE);
+    address_space.map(stack_bottom, USER_STACK_SIZE, true, false).ok_or("out of memory")?;
+    let (stack, arguments) = initial_stack(args, env).ok_or("arguments too long")?;
+    let rsp = USER_STACK_TOP - stack.len() as u64;
+    address_space.write(VirtAddr::new(rsp), &stack).ok_or("out of memory")?;
+
+    // Held until the process is registered, in case it makes a system call
+    // straight away.
+    let mut processes = PROCESSES.lock();
+    let id = thread::spawn_user(name, address_space, VirtAddr::new(elf.entry), VirtAddr::new(rsp), arguments)
+        .ok_or("too many threads")?;
+    processes.push(Process {
+        id,
+        console,
+        windows: Vec::new(),
+        next_map: USER_MAP_START,
+        mapped: 0,
+        in_syscall: false,
+        killed: false,
+    });
+    Ok(id)
+}
+
+// Builds the top of the user stack, to be copied so it ends at
+// USER_STACK_TOP, and returns it with argc, argv and envp.
+fn initial_stack(args: &[&str], env: &[String]) -> Option<(Vec<u8>, [u64; 3])> {
+    let strings = args.iter().copied().chain(env.iter().map(String::as_str));
+    let strings_size: usize = strings.clone().map(|string| string.len() + 1).sum();
+    // argc, both pointer arrays with their terminators and an AT_NULL pair.
+    let words = 1 + args.len() + 1 + env.len() + 1 + 2;
+    let size = (words * 8 + strings_size + 15) & !15;
+    if size > MAX_ARGUMENTS_SIZE {
+        return None;
+    }
+
+    let base = USER_STACK_TOP - size as u64;
+    let mut stack = vec![0u8; size];
+    let mut pointers = Vec::with_capacity(words);
+    pointers.push(args.len() as u64);
+    let mut string_offset = words * 8;
+    for (i, string) in strings.enumerate() {
+        if i == args.len() {
+            pointers.push(0);
+        }
+        pointers.push(base + string_offset as u64);
+        stack[string_offset..string_offset + string.len()].copy_from_slice(string.as_bytes());
+        string_offset += string.len() + 1;
+    }
+    if env.is_empty() {
+        pointers.push(0);
+    }
+    pointers.extend_from_slice(&[0, 0, 0]);
+    for (i, pointer) in pointers.iter().enumerate() {
+        stack[i * 8..i * 8 + 8].copy_from_slice(&pointer.to_le_bytes());
+    }
+
+    let argv = base + 8;
+    let envp = argv + (args.len() as u64 + 1) * 8;
+    Some((stack, [args.len() as u64, argv, envp]))
+}
+
+pub fn with_current<R>(f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    let id = thread::current();
+    PROCESSES.lock().iter_mut().find(|process| process.id == id).map(f)
+}
+
+// Picks the next free range for an anonymous mapping.
+pub fn reserve_map(process: &mut Process, size: u64) -> Option<u64> {
+    let size = size.checked_add(4095)? & !4095;
+    let start = process.next_map;
+    if start.checked_add(size)? > USER_MAP_END || process.mapped + size > MAX_MAPPED {
+        return None;
+    }
+    process.next_map += size;
+    process.mapped += size;
+    Some(start)
+}
+
+// Gives back the range `reserve_map` last returned, when it couldn't be mapped.
+pub fn cancel_map(process: &mut Process, start: u64) {
+    process.mapped -= process.next_map - start;
+    process.next_map = start;
+}
+
+// A process can't be killed in the middle of a system call, where it may
+// hold kernel locks; it ends when the call returns instead.
+pub fn enter_syscall() -> bool {
+    with_current(|process| process.in_syscall = true).is_some()
+}
+
+pub fn leave_syscall() {
+    let killed = with_current(|process| {
+        process.in_syscall = false;
+        process.killed
+    });
+    if killed == Some(true) {
+        exit_current();
+    }
+}
+
+pub fn kill_requested() -> bool {
+    with_current(|process| process.killed).unwrap_or(false)
+}
+
+fn remove(id: ThreadId, processes: &mut Vec<Process>) -> Option<Process> {
+    let index = processes.iter().position(|process| process.id == id)?;
+    Some(processes.remove(index))
+}
+
+fn close_windows(process: Process) {
+    if process.windows.is_empty() {
+        return;
+    }
+    let mut manager = WINDOW_MANAGER.lock();
+    for window in process.windows {
+        manager.remove_window(window);
+    }
+    drop(manager);
+    crate::task::spawn(async { crate::window_manager::dispatch_events() });
+}
+
+pub fn exit_current() -> ! {
+    let process = remove(thread::current(), &mut PROCESSES.lock());
+    if let Some(process) = process {
+        close_windows(process);
+    }
+    thread::exit();
+}
+
+pub fn kill(id: ThreadId) -> bool {
+    let mut processes = PROCESSES.lock();
+    let process = match processes.iter_mut().find(|process| process.id == id) {
+        Some(process) => process,
+        None => return false,
+    };
+    if process.in_syscall {
+        process.killed = true;
+        // It may be waiting for a key in one of its windows.
+        for window in &process.windows {
+            window.wake_waiters();
+        }
+        return true;
+    }
+    if !thread::kill(id) {
+        return false;
+    }
+    let process = remove(id, &mut processes);
+    drop(processes);
+    if let Some(process) = process {
+        close_windows(process);
+    }
+    true
+}
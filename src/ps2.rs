@@ -0,0 +1,176 @@
+use x86_64::instructions::port::Port;
+use crate::mouse;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+const STATUS_INPUT_FULL: u8 = 0x02;
+const STATUS_AUX_DATA: u8 = 0x20;
+
+const CONTROLLER_READ_CONFIG: u8 = 0x20;
+const CONTROLLER_WRITE_CONFIG: u8 = 0x60;
+const CONTROLLER_DISABLE_AUX: u8 = 0xa7;
+const CONTROLLER_SELF_TEST: u8 = 0xaa;
+const CONTROLLER_TEST_KEYBOARD: u8 = 0xab;
+const CONTROLLER_DISABLE_KEYBOARD: u8 = 0xad;
+const CONTROLLER_ENABLE_KEYBOARD: u8 = 0xae;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+pub const CONFIG_KEYBOARD_INTERRUPT: u8 = 0x01;
+pub const CONFIG_AUX_INTERRUPT: u8 = 0x02;
+pub const CONFIG_AUX_CLOCK_DISABLED: u8 = 0x20;
+const CONFIG_KEYBOARD_CLOCK_DISABLED: u8 = 0x10;
+// Has the controller turn scancode set 2 into set 1, which `pc_keyboard` decodes.
+const CONFIG_TRANSLATION: u8 = 0x40;
+
+const KEYBOARD_SET_LEDS: u8 = 0xed;
+const KEYBOARD_SET_TYPEMATIC: u8 = 0xf3;
+const KEYBOARD_ENABLE_SCANNING: u8 = 0xf4;
+const KEYBOARD_RESET: u8 = 0xff;
+
+pub const DEVICE_ACK: u8 = 0xfa;
+pub const DEVICE_RESEND: u8 = 0xfe;
+const KEYBOARD_SELF_TEST_PASSED: u8 = 0xaa;
+
+const TIMEOUT: usize = 100_000;
+const RETRIES: usize = 3;
+
+// Repeat after 500ms at about 20 characters per second.
+const TYPEMATIC_DELAY: u8 = 1;
+const TYPEMATIC_RATE: u8 = 0x04;
+
+fn wait_for_input_empty() {
+    let mut status: Port<u8> = Port::new(STATUS_PORT);
+    for _ in 0..TIMEOUT {
+        if unsafe { status.read() } & STATUS_INPUT_FULL == 0 {
+            return;
+        }
+    }
+}
+
+pub fn read_data() -> Option<u8> {
+    let mut status: Port<u8> = Port::new(STATUS_PORT);
+    let mut data: Port<u8> = Port::new(DATA_PORT);
+    for _ in 0..TIMEOUT {
+        if unsafe { status.read() } & STATUS_OUTPUT_FULL != 0 {
+            return Some(unsafe { data.read() });
+        }
+    }
+    None
+}
+
+// Like `read_data`, but hands mouse bytes that arrive in between to the
+// mouse driver instead of returning them.
+fn read_keyboard_data() -> Option<u8> {
+    let mut status: Port<u8> = Port::new(STATUS_PORT);
+    let mut data: Port<u8> = Port::new(DATA_PORT);
+    for _ in 0..TIMEOUT {
+        let flags = unsafe { status.read() };
+        if flags & STATUS_OUTPUT_FULL == 0 {
+            continue;
+        }
+        let byte = unsafe { data.read() };
+        if flags & STATUS_AUX_DATA == 0 {
+            return Some(byte);
+        }
+        mouse::handle_mouse_interrupt(byte);
+    }
+    None
+}
+
+pub fn write_command(command: u8) {
+    wait_for_input_empty();
+    unsafe { Port::new(COMMAND_PORT).write(command) };
+}
+
+pub fn write_data(byte: u8) {
+    wait_for_input_empty();
+    unsafe { Port::new(DATA_PORT).write(byte) };
+}
+
+pub fn read_config() -> u8 {
+    write_command(CONTROLLER_READ_CONFIG);
+    read_data().unwrap_or(0)
+}
+
+pub fn write_config(config: u8) {
+    write_command(CONTROLLER_WRITE_CONFIG);
+    write_data(config);
+}
+
+fn flush_output() {
+    let mut status: Port<u8> = Port::new(STATUS_PORT);
+    let mut data: Port<u8> = Port::new(DATA_PORT);
+    while unsafe { status.read() } & STATUS_OUTPUT_FULL != 0 {
+        unsafe { data.read() };
+    }
+}
+
+// Sends a byte to the keyboard, repeating it if the keyboard asks for a resend.
+fn keyboard_command(byte: u8) -> bool {
+    for _ in 0..RETRIES {
+        write_data(byte);
+        match read_keyboard_data() {
+            Some(DEVICE_ACK) => return true,
+            Some(DEVICE_RESEND) => continue,
+            _ => return false,
+        }
+    }
+    false
+}
+
+// Brings the controller and keyboard into a known state instead of relying
+// on the BIOS. Must run with interrupts disabled, before the mouse is set up.
+pub fn init() {
+    write_command(CONTROLLER_DISABLE_KEYBOARD);
+    write_command(CONTROLLER_DISABLE_AUX);
+    flush_output();
+
+    let config = read_config() & !(CONFIG_KEYBOARD_INTERRUPT | CONFIG_AUX_INTERRUPT);
+    write_config(config);
+
+    let config = (config | CONFIG_TRANSLATION) & !CONFIG_KEYBOARD_CLOCK_DISABLED;
+    if !self_test(config) {
+        // Quirky controllers may still work; don't leave them without a keyboard.
+        write_command(CONTROLLER_ENABLE_KEYBOARD);
+        write_config(config | CONFIG_KEYBOARD_INTERRUPT);
+        return;
+    }
+    write_command(CONTROLLER_ENABLE_KEYBOARD);
+
+    if keyboard_command(KEYBOARD_RESET) && read_data() == Some(KEYBOARD_SELF_TEST_PASSED) {
+        keyboard_command(KEYBOARD_SET_TYPEMATIC);
+        keyboard_command(TYPEMATIC_DELAY << 5 | TYPEMATIC_RATE);
+        keyboard_command(KEYBOARD_ENABLE_SCANNING);
+    }
+    flush_output();
+
+    write_config(config | CONFIG_KEYBOARD_INTERRUPT);
+}
+
+fn self_test(config: u8) -> bool {
+    write_command(CONTROLLER_SELF_TEST);
+    if read_data() != Some(SELF_TEST_PASSED) {
+        return false;
+    }
+    // Some controllers reset their configuration during the self-test.
+    write_config(config);
+
+    write_command(CONTROLLER_TEST_KEYBOARD);
+    read_data() == Some(PORT_TEST_PASSED)
+}
+
+// Interrupts are held off so the keyboard's acknowledgements come back here
+// rather than through the keyboard handler.
+pub fn set_leds(scroll_lock: bool, num_lock: bool, caps_lock: bool) {
+    let leds = u8::from(scroll_lock) | u8::from(num_lock) << 1 | u8::from(caps_lock) << 2;
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        if keyboard_command(KEYBOARD_SET_LEDS) {
+            keyboard_command(leds);
+        }
+    });
+}
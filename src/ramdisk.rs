@@ -0,0 +1,120 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::sync::Mutex;
+use lazy_static::lazy_static;
+
+pub struct RamDisk {
+    files: BTreeMap<String, Vec<u8>>,
+    dirs: BTreeSet<String>,
+}
+
+impl RamDisk {
+    pub fn new() -> Self {
+        let mut dirs = BTreeSet::new();
+        dirs.insert(String::from("/"));
+        RamDisk {
+            files: BTreeMap::new(),
+            dirs,
+        }
+    }
+
+    pub fn read(&self, path: &str) -> Option<&[u8]> {
+        self.files.get(path).map(|data| data.as_slice())
+    }
+
+    pub fn write(&mut self, path: &str, data: &[u8]) -> bool {
+        if !self.is_dir(parent(path)) || self.is_dir(path) {
+            return false;
+        }
+        self.files.insert(String::from(path), Vec::from(data));
+        true
+    }
+
+    pub fn append(&mut self, path: &str, data: &[u8]) -> bool {
+        if !self.is_dir(parent(path)) || self.is_dir(path) {
+            return false;
+        }
+        self.files
+            .entry(String::from(path))
+            .or_default()
+            .extend_from_slice(data);
+        true
+    }
+
+    pub fn remove(&mut self, path: &str) -> bool {
+        self.files.remove(path).is_some()
+    }
+
+    pub fn mkdir(&mut self, path: &str) -> bool {
+        if !self.is_dir(parent(path)) || self.files.contains_key(path) {
+            return false;
+        }
+        self.dirs.insert(String::from(path))
+    }
+
+    pub fn is_dir(&self, path: &str) -> bool {
+        self.dirs.contains(path)
+    }
+
+    pub fn list(&self, dir: &str) -> Vec<(String, Option<usize>)> {
+        let dirs = self.dirs.iter().map(|path| (path, None));
+        let files = self.files.iter().map(|(path, data)| (path, Some(data.len())));
+
+        dirs.chain(files)
+            .filter(|(path, _)| path.as_str() != "/" && parent(path) == dir)
+            .map(|(path, size)| (String::from(file_name(path)), size))
+            .collect()
+    }
+}
+
+pub fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    }
+}
+
+pub fn file_name(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(i) => &path[i + 1..],
+        None => path,
+    }
+}
+
+pub fn resolve(cwd: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    let base = if path.starts_with('/') { "" } else { cwd };
+
+    for part in base.split('/').chain(path.split('/')) {
+        match part {
+            "" | "." => {},
+            ".." => {
+                parts.pop();
+            },
+            part => parts.push(part),
+        }
+    }
+
+    let mut resolved = String::new();
+    for part in parts {
+        resolved.push('/');
+        resolved.push_str(part);
+    }
+    if resolved.is_empty() {
+        resolved.push('/');
+    }
+    resolved
+}
+
+pub fn init() {
+    let mut ramdisk = RAMDISK.lock();
+    ramdisk.mkdir("/etc");
+    ramdisk.write(crate::script::BOOT_SCRIPT_PATH, crate::script::BOOT_SCRIPT.as_bytes());
+    ramdisk.mkdir("/etc/keymaps");
+    ramdisk.write(crate::keyboard::GERMAN_KEYMAP_PATH, crate::keyboard::GERMAN_KEYMAP.as_bytes());
+}
+
+lazy_static! {
+    pub static ref RAMDISK: Mutex<RamDisk> = Mutex::new(RamDisk::new());
+}
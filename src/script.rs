@@ -0,0 +1,319 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::shell::{expand, is_valid_name, split_words, STATUS_SUCCESS, STATUS_SYNTAX_ERROR};
+use crate::terminal::Terminal;
+
+pub const BOOT_SCRIPT_PATH: &str = "/etc/rc";
+pub const BOOT_SCRIPT: &str = include_str!("boot.sh");
+
+enum Stmt {
+    Simple(String),
+    If {
+        branches: Vec<(String, Vec<Stmt>)>,
+        otherwise: Vec<Stmt>,
+    },
+    While {
+        condition: String,
+        body: Vec<Stmt>,
+    },
+    For {
+        var: String,
+        items: Vec<String>,
+        body: Vec<Stmt>,
+    },
+}
+
+struct Segment<'a> {
+    line: usize,
+    text: &'a str,
+}
+
+struct ParseError {
+    line: usize,
+    message: &'static str,
+}
+
+const KEYWORDS: [&str; 9] = ["if", "then", "elif", "else", "fi", "while", "do", "done", "for"];
+
+fn keyword(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim();
+    let (word, rest) = match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    };
+    KEYWORDS.iter().find(|&&keyword| keyword == word).map(|_| (word, rest))
+}
+
+// Splits script source into `;`/newline separated statements, dropping comments.
+fn split_segments(source: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+
+    for (number, line) in source.lines().enumerate() {
+        let mut start = 0;
+        let mut quote = None;
+        let mut escaped = false;
+        let mut end = line.len();
+
+        for (i, c) in line.char_indices() {
+            if escaped {
+                escaped = false;
+                continue;
+            }
+            match (quote, c) {
+                (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+                (Some('"'), '\\') | (None, '\\') => escaped = true,
+                (Some(_), _) => {},
+                (None, '\'') | (None, '"') => quote = Some(c),
+                (None, '#') if i == 0 || line[..i].ends_with(char::is_whitespace) || line[..i].ends_with(';') => {
+                    end = i;
+                    break;
+                },
+                (None, ';') => {
+                    segments.push(Segment { line: number + 1, text: line[start..i].trim() });
+                    start = i + 1;
+                },
+                _ => {},
+            }
+        }
+
+        segments.push(Segment { line: number + 1, text: line[start..end].trim() });
+    }
+
+    segments.retain(|segment| !segment.text.is_empty());
+    segments
+}
+
+struct Parser<'a> {
+    segments: Vec<Segment<'a>>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Self {
+        let mut segments = Vec::new();
+        // `then`, `do` and `else` may be followed by a command on the same segment.
+        for segment in split_segments(source) {
+            match keyword(segment.text) {
+                Some((word @ ("then" | "do" | "else"), rest)) if !rest.is_empty() => {
+                    segments.push(Segment { line: segment.line, text: word });
+                    segments.push(Segment { line: segment.line, text: rest });
+                },
+                _ => segments.push(segment),
+            }
+        }
+        Parser { segments, pos: 0 }
+    }
+
+    fn line(&self) -> usize {
+        self.segments
+            .get(self.pos)
+            .or(self.segments.last())
+            .map_or(0, |segment| segment.line)
+    }
+
+    fn error(&self, message: &'static str) -> ParseError {
+        ParseError { line: self.line(), message }
+    }
+
+    fn expect(&mut self, expected: &str, message: &'static str) -> Result<(), ParseError> {
+        match self.segments.get(self.pos).and_then(|segment| keyword(segment.text)) {
+            Some((word, "")) if word == expected => {
+                self.pos += 1;
+                Ok(())
+            },
+            _ => Err(self.error(message)),
+        }
+    }
+
+    fn parse_block(&mut self, terminators: &[&str]) -> Result<Vec<Stmt>, ParseError> {
+        let mut stmts = Vec::new();
+
+        while let Some(segment) = self.segments.get(self.pos) {
+            let text = segment.text;
+            match keyword(text) {
+                Some((word, _)) if terminators.contains(&word) => return Ok(stmts),
+                Some(("if", condition)) => {
+                    self.pos += 1;
+                    stmts.push(self.parse_if(condition)?);
+                },
+                Some(("while", condition)) => {
+                    self.pos += 1;
+                    stmts.push(self.parse_while(condition)?);
+                },
+                Some(("for", header)) => {
+                    self.pos += 1;
+                    stmts.push(self.parse_for(header)?);
+                },
+                Some(_) => return Err(self.error("unexpected keyword")),
+                None => {
+                    self.pos += 1;
+                    stmts.push(Stmt::Simple(String::from(text)));
+                },
+            }
+        }
+
+        if terminators.is_empty() {
+            Ok(stmts)
+        } else {
+            Err(self.error("unexpected end of script"))
+        }
+    }
+
+    fn parse_if(&mut self, condition: &str) -> Result<Stmt, ParseError> {
+        if condition.is_empty() {
+            return Err(self.error("expected condition after 'if'"));
+        }
+
+        let mut branches = Vec::new();
+        let mut condition = String::from(condition);
+
+        loop {
+            self.expect("then", "expected 'then'")?;
+            let body = self.parse_block(&["elif", "else", "fi"])?;
+            branches.push((condition, body));
+
+            let segment = &self.segments[self.pos];
+            match keyword(segment.text) {
+                Some(("elif", next)) if !next.is_empty() => {
+                    condition = String::from(next);
+                    self.pos += 1;
+                },
+                Some(("else", "")) => {
+                    self.pos += 1;
+                    let otherwise = self.parse_block(&["fi"])?;
+                    self.expect("fi", "expected 'fi'")?;
+                    return Ok(Stmt::If { branches, otherwise });
+                },
+                Some(("fi", "")) => {
+                    self.pos += 1;
+                    return Ok(Stmt::If { branches, otherwise: Vec::new() });
+                },
+                _ => return Err(self.error("expected 'elif', 'else' or 'fi'")),
+            }
+        }
+    }
+
+    fn parse_while(&mut self, condition: &str) -> Result<Stmt, ParseError> {
+        if condition.is_empty() {
+            return Err(self.error("expected condition after 'while'"));
+        }
+
+        self.expect("do", "expected 'do'")?;
+        let body = self.parse_block(&["done"])?;
+        self.expect("done", "expected 'done'")?;
+
+        Ok(Stmt::While { condition: String::from(condition), body })
+    }
+
+    fn parse_for(&mut self, header: &str) -> Result<Stmt, ParseError> {
+        let mut words = split_words(header).map_err(|message| self.error(message))?.into_iter();
+        let var = match words.next() {
+            Some(var) if is_valid_name(&var) => var,
+            _ => return Err(self.error("expected variable name after 'for'")),
+        };
+        if words.next().as_deref() != Some("in") {
+            return Err(self.error("expected 'in'"));
+        }
+        let items = words.collect();
+
+        self.expect("do", "expected 'do'")?;
+        let body = self.parse_block(&["done"])?;
+        self.expect("done", "expected 'done'")?;
+
+        Ok(Stmt::For { var, items, body })
+    }
+}
+
+impl Terminal {
+    pub fn run_source(&mut self, source: &str) -> i32 {
+        match Parser::new(source).parse_block(&[]) {
+            Ok(stmts) => self.run_block(&stmts),
+            Err(error) => {
+                let _ = writeln!(self, "line {}: syntax error: {}", error.line, error.message);
+                self.last_status = STATUS_SYNTAX_ERROR;
+                STATUS_SYNTAX_ERROR
+            }
+        }
+    }
+
+    pub fn run_script(&mut self, source: &str, args: Vec<String>) -> i32 {
+        let saved_args = core::mem::replace(&mut self.args, args);
+        self.script_depth += 1;
+
+        let status = self.run_source(source);
+
+        self.script_depth -= 1;
+        self.args = saved_args;
+        let status = self.exit_status.take().unwrap_or(status);
+        self.last_status = status;
+        status
+    }
+
+    fn run_block(&mut self, stmts: &[Stmt]) -> i32 {
+        let mut status = STATUS_SUCCESS;
+
+        for stmt in stmts {
+            status = match stmt {
+                Stmt::Simple(line) => self.run_line(line),
+                Stmt::If { branches, otherwise } => {
+                    let mut taken = None;
+                    for (condition, body) in branches {
+                        let passed = self.run_line(condition) == STATUS_SUCCESS;
+                        if self.exit_status.is_some() {
+                            break;
+                        }
+                        if passed {
+                            taken = Some(body);
+                            break;
+                        }
+                    }
+                    match taken {
+                        Some(body) => self.run_block(body),
+                        None if self.exit_status.is_none() => self.run_block(otherwise),
+                        None => STATUS_SUCCESS,
+                    }
+                },
+                Stmt::While { condition, body } => {
+                    let mut status = STATUS_SUCCESS;
+                    while self.run_line(condition) == STATUS_SUCCESS && self.exit_status.is_none() {
+                        status = self.run_block(body);
+                        if self.exit_status.is_some() {
+                            break;
+                        }
+                    }
+                    status
+                },
+                Stmt::For { var, items, body } => {
+                    let mut values = Vec::new();
+                    for item in items {
+                        match expand(item, &|name| self.get_var(name)) {
+                            Ok(fields) => values.extend(fields),
+                            Err(message) => {
+                                let _ = writeln!(self, "syntax error: {}", message);
+                                return STATUS_SYNTAX_ERROR;
+                            }
+                        }
+                    }
+
+                    let mut status = STATUS_SUCCESS;
+                    for value in values {
+                        self.set_var(var, &value);
+                        status = self.run_block(body);
+                        if self.exit_status.is_some() {
+                            break;
+                        }
+                    }
+                    status
+                },
+            };
+
+            self.last_status = status;
+            if self.exit_status.is_some() {
+                break;
+            }
+        }
+
+        status
+    }
+}
@@ -0,0 +1,492 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::iter::Peekable;
+use core::str::Chars;
+use crate::config::{self, UiMode};
+use crate::ramdisk::{self, RAMDISK};
+use crate::terminal::{Terminal, Variable};
+use crate::time;
+
+pub const STATUS_SUCCESS: i32 = 0;
+pub const STATUS_FAILURE: i32 = 1;
+pub const STATUS_SYNTAX_ERROR: i32 = 2;
+pub const STATUS_NOT_FOUND: i32 = 127;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Pipe,
+    And,
+    Or,
+    Semicolon,
+    Redirect { append: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Connector {
+    Always,
+    IfSuccess,
+    IfFailure,
+}
+
+struct Redirect {
+    target: String,
+    append: bool,
+}
+
+struct Command {
+    argv: Vec<String>,
+    redirect: Option<Redirect>,
+}
+
+struct Pipeline {
+    connector: Connector,
+    commands: Vec<Command>,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(is_name_char)
+}
+
+fn read_variable_name(chars: &mut Peekable<Chars>) -> Result<String, &'static str> {
+    let mut name = String::new();
+    match chars.peek() {
+        Some(&c @ ('?' | '#')) => {
+            chars.next();
+            name.push(c);
+        },
+        Some('{') => {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some(c) => name.push(c),
+                    None => return Err("unterminated '${'"),
+                }
+            }
+        },
+        _ => {
+            while let Some(&c) = chars.peek() {
+                if !is_name_char(c) {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+        },
+    }
+    Ok(name)
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, &'static str> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    let mut word = String::new();
+
+    while let Some(c) = chars.next() {
+        let operator = match c {
+            '|' if chars.peek() == Some(&'|') => {
+                chars.next();
+                Some(Token::Or)
+            },
+            '|' => Some(Token::Pipe),
+            '&' if chars.peek() == Some(&'&') => {
+                chars.next();
+                Some(Token::And)
+            },
+            ';' => Some(Token::Semicolon),
+            '>' if chars.peek() == Some(&'>') => {
+                chars.next();
+                Some(Token::Redirect { append: true })
+            },
+            '>' => Some(Token::Redirect { append: false }),
+            _ => None,
+        };
+
+        if operator.is_some() || c.is_whitespace() {
+            if !word.is_empty() {
+                tokens.push(Token::Word(core::mem::take(&mut word)));
+            }
+            if let Some(operator) = operator {
+                tokens.push(operator);
+            }
+            continue;
+        }
+
+        // Words are kept verbatim; quotes and variables are handled by `expand`
+        // when the command runs, so `$?` sees the status of the previous command.
+        word.push(c);
+        match c {
+            '\'' | '"' => {
+                let quote = c;
+                loop {
+                    match chars.next() {
+                        Some(c) => {
+                            word.push(c);
+                            if c == quote {
+                                break;
+                            }
+                            if c == '\\' && quote == '"' {
+                                if let Some(escaped) = chars.next() {
+                                    word.push(escaped);
+                                }
+                            }
+                        },
+                        None => return Err("unterminated quote"),
+                    }
+                }
+            },
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    if !word.is_empty() {
+        tokens.push(Token::Word(word));
+    }
+
+    Ok(tokens)
+}
+
+pub fn split_words(text: &str) -> Result<Vec<String>, &'static str> {
+    tokenize(text)?
+        .into_iter()
+        .map(|token| match token {
+            Token::Word(word) => Ok(word),
+            _ => Err("unexpected operator"),
+        })
+        .collect()
+}
+
+pub fn expand(word: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Vec<String>, &'static str> {
+    let mut words = Vec::new();
+    let mut chars = word.chars().peekable();
+    let mut current = String::new();
+    let mut in_word = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => current.push(c),
+                        None => return Err("unterminated quote"),
+                    }
+                }
+            },
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('$') => {
+                            let name = read_variable_name(&mut chars)?;
+                            if name.is_empty() {
+                                current.push('$');
+                            } else {
+                                current.push_str(&lookup(&name).unwrap_or_default());
+                            }
+                        },
+                        Some('\\') => match chars.next() {
+                            Some(c) => current.push(c),
+                            None => return Err("unterminated quote"),
+                        },
+                        Some(c) => current.push(c),
+                        None => return Err("unterminated quote"),
+                    }
+                }
+            },
+            '$' => {
+                let name = read_variable_name(&mut chars)?;
+                if name.is_empty() {
+                    in_word = true;
+                    current.push('$');
+                    continue;
+                }
+                for c in lookup(&name).unwrap_or_default().chars() {
+                    if c.is_whitespace() {
+                        if in_word {
+                            words.push(core::mem::take(&mut current));
+                            in_word = false;
+                        }
+                    } else {
+                        in_word = true;
+                        current.push(c);
+                    }
+                }
+            },
+            '\\' => {
+                in_word = true;
+                if let Some(c) = chars.next() {
+                    current.push(c);
+                }
+            },
+            _ => {
+                in_word = true;
+                current.push(c);
+            },
+        }
+    }
+
+    if in_word {
+        words.push(current);
+    }
+
+    Ok(words)
+}
+
+fn parse(tokens: Vec<Token>) -> Result<Vec<Pipeline>, &'static str> {
+    let mut pipelines = Vec::new();
+    let mut commands = Vec::new();
+    let mut argv = Vec::new();
+    let mut redirect = None;
+    let mut connector = Connector::Always;
+    let mut tokens = tokens.into_iter();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Word(word) => argv.push(word),
+            Token::Redirect { append } => match tokens.next() {
+                Some(Token::Word(target)) => redirect = Some(Redirect { target, append }),
+                _ => return Err("expected file name after redirection"),
+            },
+            Token::Pipe => {
+                if argv.is_empty() {
+                    return Err("expected command before '|'");
+                }
+                commands.push(Command { argv: core::mem::take(&mut argv), redirect: redirect.take() });
+            },
+            Token::And | Token::Or | Token::Semicolon => {
+                if argv.is_empty() {
+                    if commands.is_empty() && token == Token::Semicolon {
+                        continue;
+                    }
+                    return Err("expected command before operator");
+                }
+                commands.push(Command { argv: core::mem::take(&mut argv), redirect: redirect.take() });
+                pipelines.push(Pipeline { connector, commands: core::mem::take(&mut commands) });
+                connector = match token {
+                    Token::And => Connector::IfSuccess,
+                    Token::Or => Connector::IfFailure,
+                    _ => Connector::Always,
+                };
+            },
+        }
+    }
+
+    if argv.is_empty() {
+        if !commands.is_empty() || connector != Connector::Always {
+            return Err("unexpected end of line");
+        }
+        if redirect.is_some() {
+            return Err("expected command before redirection");
+        }
+    } else {
+        commands.push(Command { argv, redirect });
+        pipelines.push(Pipeline { connector, commands });
+    }
+
+    Ok(pipelines)
+}
+
+impl Terminal {
+    pub fn get_var(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(format!("{}", self.last_status)),
+            "#" => Some(format!("{}", self.args.len().saturating_sub(1))),
+            "PWD" => Some(self.cwd.clone()),
+            _ if name.chars().all(|c| c.is_ascii_digit()) => {
+                name.parse::<usize>().ok().and_then(|i| self.args.get(i).cloned())
+            },
+            _ => self.vars.get(name).map(|var| var.value.clone()),
+        }
+    }
+
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.value = String::from(value),
+            None => {
+                self.vars.insert(String::from(name), Variable { value: String::from(value), exported: false });
+            },
+        }
+    }
+
+    pub fn unset_var(&mut self, name: &str) -> bool {
+        self.vars.remove(name).is_some()
+    }
+
+    pub fn export_var(&mut self, name: &str) {
+        self.vars
+            .entry(String::from(name))
+            .or_insert_with(|| Variable { value: String::new(), exported: false })
+            .exported = true;
+    }
+
+    pub fn resolve_path(&self, path: &str) -> String {
+        ramdisk::resolve(&self.cwd, path)
+    }
+
+    pub fn render_prompt(&self) -> String {
+        let ps1 = self.get_var("PS1").unwrap_or_default();
+        let mut prompt = String::new();
+        let mut chars = ps1.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                prompt.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('t') => {
+                    let seconds = time::uptime_seconds();
+                    let _ = write!(prompt, "{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60);
+                },
+                Some('m') => prompt.push_str(match config::get_current_ui_mode() {
+                    UiMode::Windowed => "windowed",
+                    UiMode::FullscreenTerminal => "fullscreen",
+                }),
+                Some('w') => prompt.push_str(&self.cwd),
+                Some('W') => prompt.push_str(match ramdisk::file_name(&self.cwd) {
+                    "" => "/",
+                    name => name,
+                }),
+                Some('n') => prompt.push('\n'),
+                Some('$') => prompt.push('$'),
+                Some('\\') | None => prompt.push('\\'),
+                Some(other) => {
+                    prompt.push('\\');
+                    prompt.push(other);
+                },
+            }
+        }
+
+        prompt
+    }
+
+    pub fn run_line(&mut self, line: &str) -> i32 {
+        let pipelines = match tokenize(line).and_then(parse) {
+            Ok(pipelines) => pipelines,
+            Err(message) => {
+                let _ = writeln!(self, "syntax error: {}", message);
+                self.last_status = STATUS_SYNTAX_ERROR;
+                return STATUS_SYNTAX_ERROR;
+            }
+        };
+
+        for pipeline in pipelines {
+            let skip = match pipeline.connector {
+                Connector::Always => false,
+                Connector::IfSuccess => self.last_status != STATUS_SUCCESS,
+                Connector::IfFailure => self.last_status == STATUS_SUCCESS,
+            };
+            if !skip {
+                self.last_status = self.run_pipeline(&pipeline);
+            }
+        }
+
+        self.last_status
+    }
+
+    fn expand_words(&mut self, words: &[String]) -> Result<Vec<String>, i32> {
+        let mut expanded = Vec::new();
+        for word in words {
+            match expand(word, &|name| self.get_var(name)) {
+                Ok(fields) => expanded.extend(fields),
+                Err(message) => {
+                    let _ = writeln!(self, "syntax error: {}", message);
+                    return Err(STATUS_SYNTAX_ERROR);
+                }
+            }
+        }
+        Ok(expanded)
+    }
+
+    fn run_pipeline(&mut self, pipeline: &Pipeline) -> i32 {
+        let mut status = STATUS_SUCCESS;
+        let mut input = None;
+        let last = pipeline.commands.len() - 1;
+
+        for (i, command) in pipeline.commands.iter().enumerate() {
+            let argv = match self.expand_words(&command.argv) {
+                Ok(argv) => argv,
+                Err(status) => return status,
+            };
+            let target = match &command.redirect {
+                Some(redirect) => match self.expand_words(core::slice::from_ref(&redirect.target)) {
+                    Ok(fields) if fields.len() == 1 => Some(self.resolve_path(&fields[0])),
+                    Ok(_) => {
+                        let _ = writeln!(self, "{}: ambiguous redirect", redirect.target);
+                        return STATUS_FAILURE;
+                    },
+                    Err(status) => return status,
+                },
+                None => None,
+            };
+            let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+            let capture = i < last || target.is_some();
+
+            let saved_capture = if capture { self.capture.replace(String::new()) } else { None };
+            let saved_stdin = core::mem::replace(&mut self.stdin, input.take());
+
+            status = match argv.split_first() {
+                Some((assignment, [])) if assignment.contains('=') => self.assign(assignment),
+                _ => self.execute_command(&argv),
+            };
+
+            self.stdin = saved_stdin;
+            let output = if capture {
+                core::mem::replace(&mut self.capture, saved_capture)
+            } else {
+                None
+            };
+
+            match (target, output) {
+                (Some(target), Some(output)) => {
+                    let append = command.redirect.as_ref().is_some_and(|redirect| redirect.append);
+                    let mut ramdisk = RAMDISK.lock();
+                    let written = if append {
+                        ramdisk.append(&target, output.as_bytes())
+                    } else {
+                        ramdisk.write(&target, output.as_bytes())
+                    };
+                    drop(ramdisk);
+                    if !written {
+                        let _ = writeln!(self, "{}: cannot write file", target);
+                        status = STATUS_FAILURE;
+                    }
+                },
+                (None, output) => input = output,
+                (Some(_), None) => {},
+            }
+        }
+
+        status
+    }
+
+    pub fn assign(&mut self, assignment: &str) -> i32 {
+        match assignment.split_once('=') {
+            Some((name, value)) if is_valid_name(name) => {
+                self.set_var(name, value);
+                STATUS_SUCCESS
+            },
+            _ => {
+                let _ = writeln!(self, "{}: not a valid assignment", assignment);
+                STATUS_FAILURE
+            },
+        }
+    }
+}
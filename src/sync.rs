@@ -0,0 +1,249 @@
+use core::cell::UnsafeCell;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use x86_64::instructions::interrupts;
+use crate::thread::{self, ThreadId, MAX_THREADS};
+
+// A spinlock that keeps interrupts disabled while held, so an interrupt
+// handler can never spin on a lock the code it interrupted owns. Use it for
+// anything handlers touch and keep the critical sections short.
+pub struct IrqMutex<T> {
+    inner: spin::Mutex<T>,
+}
+
+pub struct IrqMutexGuard<'a, T> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    interrupts_enabled: bool,
+}
+
+impl<T> IrqMutex<T> {
+    pub const fn new(value: T) -> Self {
+        IrqMutex { inner: spin::Mutex::new(value) }
+    }
+
+    pub fn lock(&self) -> IrqMutexGuard<'_, T> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        IrqMutexGuard { guard: ManuallyDrop::new(self.inner.lock()), interrupts_enabled }
+    }
+}
+
+impl<T> Deref for IrqMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for IrqMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for IrqMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Unlock before interrupts come back on.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+// Threads blocked until someone wakes them, in the order they started
+// waiting. Fixed size so waking never allocates and works from interrupts.
+pub struct WaitQueue {
+    waiters: IrqMutex<([Option<ThreadId>; MAX_THREADS], usize)>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue { waiters: IrqMutex::new(([None; MAX_THREADS], 0)) }
+    }
+
+    // Blocks the current thread, releasing `guard` only once the thread is
+    // queued so a wakeup sent after the release can't be missed. Callers
+    // recheck their condition afterwards; wakeups may be spurious.
+    pub fn wait<G>(&self, guard: G) {
+        {
+            let mut waiters = self.waiters.lock();
+            let (queue, len) = &mut *waiters;
+            if *len < MAX_THREADS {
+                queue[*len] = Some(thread::current());
+                *len += 1;
+                thread::block_current();
+            }
+        }
+        drop(guard);
+        thread::yield_now();
+    }
+
+    fn pop(&self) -> Option<ThreadId> {
+        let mut waiters = self.waiters.lock();
+        let (queue, len) = &mut *waiters;
+        if *len == 0 {
+            return None;
+        }
+        let waiter = queue[0].take();
+        queue[..*len].rotate_left(1);
+        *len -= 1;
+        waiter
+    }
+
+    // Skips waiters that have gone away so the wakeup isn't lost.
+    pub fn wake_one(&self) -> bool {
+        while let Some(id) = self.pop() {
+            if thread::unblock(id) {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn wake_all(&self) {
+        while let Some(id) = self.pop() {
+            thread::unblock(id);
+        }
+    }
+}
+
+// A lock that blocks the waiting thread instead of spinning, so whoever holds
+// it can finish even while preempted. Must not be taken from interrupt handlers.
+pub struct Mutex<T> {
+    locked: IrqMutex<bool>,
+    waiters: WaitQueue,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Mutex { locked: IrqMutex::new(false), waiters: WaitQueue::new(), value: UnsafeCell::new(value) }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        loop {
+            let mut locked = self.locked.lock();
+            if !*locked {
+                *locked = true;
+                return MutexGuard { mutex: self };
+            }
+            self.waiters.wait(locked);
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let mut locked = self.locked.lock();
+        if *locked {
+            return None;
+        }
+        *locked = true;
+        Some(MutexGuard { mutex: self })
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        *self.mutex.locked.lock() = false;
+        self.mutex.waiters.wake_one();
+    }
+}
+
+#[allow(dead_code)]
+pub struct Semaphore {
+    count: IrqMutex<usize>,
+    waiters: WaitQueue,
+}
+
+#[allow(dead_code)]
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Semaphore { count: IrqMutex::new(count), waiters: WaitQueue::new() }
+    }
+
+    pub fn acquire(&self) {
+        loop {
+            let mut count = self.count.lock();
+            if *count > 0 {
+                *count -= 1;
+                return;
+            }
+            self.waiters.wait(count);
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        let mut count = self.count.lock();
+        if *count == 0 {
+            return false;
+        }
+        *count -= 1;
+        true
+    }
+
+    // Safe to call from interrupt handlers.
+    pub fn release(&self) {
+        *self.count.lock() += 1;
+        self.waiters.wake_one();
+    }
+}
+
+#[allow(dead_code)]
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+#[allow(dead_code)]
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar { waiters: WaitQueue::new() }
+    }
+
+    // Releases the mutex while waiting and takes it again before returning.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        self.waiters.wait(guard);
+        mutex.lock()
+    }
+
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) {
+        self.waiters.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+    }
+}
@@ -0,0 +1,283 @@
+use alloc::string::String;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
+use crate::compositor::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::config::{self, UiMode};
+use crate::events::Event;
+use crate::memory;
+use crate::process;
+use crate::terminal;
+use crate::thread;
+use crate::vga::Color;
+use crate::window_manager::{Window, WindowHandle, WINDOW_MANAGER};
+
+// System call numbers, passed in rax. Arguments go in rdi, rsi, rdx, r10 and
+// r8 and the result comes back in rax.
+pub const SYS_WRITE: u64 = 0;
+pub const SYS_READ_KEY: u64 = 1;
+pub const SYS_WINDOW_CREATE: u64 = 2;
+pub const SYS_WINDOW_PRINT: u64 = 3;
+pub const SYS_SLEEP: u64 = 4;
+pub const SYS_EXIT: u64 = 5;
+pub const SYS_MAP: u64 = 6;
+pub const SYS_POLL_KEY: u64 = 7;
+
+// Errors are returned as small negative numbers.
+pub const ERR_BAD_POINTER: u64 = -1i64 as u64;
+pub const ERR_INVALID_ARGUMENT: u64 = -2i64 as u64;
+pub const ERR_UNKNOWN_SYSCALL: u64 = -3i64 as u64;
+pub const ERR_NO_MEMORY: u64 = -4i64 as u64;
+pub const ERR_INTERRUPTED: u64 = -5i64 as u64;
+
+const MAX_TEXT_LENGTH: u64 = 4096;
+// A day.
+const MAX_SLEEP_TICKS: u64 = 24 * 60 * 60 * crate::time::TICKS_PER_SECOND;
+
+type Handler = fn([u64; 5]) -> u64;
+
+// Top of the running thread's kernel stack, which `syscall` doesn't switch to
+// by itself, and a scratch slot for the user stack pointer while it does.
+static KERNEL_STACK: AtomicU64 = AtomicU64::new(0);
+static USER_STACK: AtomicU64 = AtomicU64::new(0);
+
+pub fn init() {
+    let selectors = crate::gdt::selectors();
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+    Star::write(selectors.user_code, selectors.user_data, selectors.kernel_code, selectors.kernel_data)
+        .expect("GDT order doesn't suit sysret");
+    LStar::write(VirtAddr::new(syscall_entry as *const () as u64));
+    // Enter with interrupts off until we're on the kernel stack.
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+}
+
+pub fn set_kernel_stack(top: VirtAddr) {
+    KERNEL_STACK.store(top.as_u64(), Ordering::Relaxed);
+}
+
+// `syscall` leaves the user's return address in rcx and flags in r11. The
+// registers besides rax, rcx and r11 are preserved for the caller.
+#[unsafe(naked)]
+extern "C" fn syscall_entry() {
+    core::arch::naked_asm!(
+        "mov [rip + {user_stack}], rsp",
+        "mov rsp, [rip + {kernel_stack}]",
+        "push qword ptr [rip + {user_stack}]",
+        "push rcx",
+        "push r11",
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push r8",
+        "push r9",
+        "push r10",
+        "sub rsp, 8",
+        // Shuffle the syscall registers into the C calling convention.
+        "mov r9, r8",
+        "mov r8, r10",
+        "mov rcx, rdx",
+        "mov rdx, rsi",
+        "mov rsi, rdi",
+        "mov rdi, rax",
+        "sti",
+        "call {dispatch}",
+        "cli",
+        "add rsp, 8",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        "pop r11",
+        "pop rcx",
+        "pop rsp",
+        "sysretq",
+        user_stack = sym USER_STACK,
+        kernel_stack = sym KERNEL_STACK,
+        dispatch = sym dispatch,
+    );
+}
+
+extern "C" fn dispatch(number: u64, a0: u64, a1: u64, a2: u64, a3: u64, a4: u64) -> u64 {
+    if !process::enter_syscall() {
+        return ERR_UNKNOWN_SYSCALL;
+    }
+    let result = match handler(number) {
+        Some(handler) => handler([a0, a1, a2, a3, a4]),
+        None => ERR_UNKNOWN_SYSCALL,
+    };
+    process::leave_syscall();
+    result
+}
+
+fn handler(number: u64) -> Option<Handler> {
+    let handler: Handler = match number {
+        SYS_WRITE => sys_write,
+        SYS_READ_KEY => sys_read_key,
+        SYS_WINDOW_CREATE => sys_window_create,
+        SYS_WINDOW_PRINT => sys_window_print,
+        SYS_SLEEP => sys_sleep,
+        SYS_EXIT => sys_exit,
+        SYS_MAP => sys_map,
+        SYS_POLL_KEY => sys_poll_key,
+        _ => return None,
+    };
+    Some(handler)
+}
+
+// Copies a string out of the caller's memory, refusing anything outside its
+// own user mappings.
+fn user_string(pointer: u64, length: u64) -> Result<String, u64> {
+    if length > MAX_TEXT_LENGTH {
+        return Err(ERR_INVALID_ARGUMENT);
+    }
+    if !memory::user_range_accessible(pointer, length, false) {
+        return Err(ERR_BAD_POINTER);
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(pointer as *const u8, length as usize) };
+    Ok(String::from_utf8_lossy(bytes).into_owned())
+}
+
+// write(text, length): prints to the terminal that started the process.
+fn sys_write([pointer, length, ..]: [u64; 5]) -> u64 {
+    let text = match user_string(pointer, length) {
+        Ok(text) => text,
+        Err(error) => return error,
+    };
+    if let Some(Some(console)) = process::with_current(|process| process.console) {
+        terminal::write_process_output(console, &text);
+    }
+    length
+}
+
+// read_key(window): waits for a key typed into one of the process's windows
+// and returns its character.
+fn sys_read_key([window, ..]: [u64; 5]) -> u64 {
+    let handle = match process::with_current(|process| process.windows.get(window as usize).copied()) {
+        Some(Some(handle)) => handle,
+        _ => return ERR_INVALID_ARGUMENT,
+    };
+    loop {
+        if let Some(character) = next_typed_char(handle) {
+            return character as u64;
+        }
+        if handle.size().is_none() {
+            return ERR_INVALID_ARGUMENT;
+        }
+        if process::kill_requested() {
+            return ERR_INTERRUPTED;
+        }
+        handle.wait_event();
+    }
+}
+
+// poll_key(window): like read_key, but returns 0 straight away when no key
+// is waiting.
+fn sys_poll_key([window, ..]: [u64; 5]) -> u64 {
+    let handle = match process::with_current(|process| process.windows.get(window as usize).copied()) {
+        Some(Some(handle)) => handle,
+        _ => return ERR_INVALID_ARGUMENT,
+    };
+    if handle.size().is_none() {
+        return ERR_INVALID_ARGUMENT;
+    }
+    next_typed_char(handle).map_or(0, |character| character as u64)
+}
+
+fn next_typed_char(handle: WindowHandle) -> Option<char> {
+    while let Some(event) = handle.poll_event() {
+        if let Event::Key(key) = event {
+            if let Some(character) = key.typed_char() {
+                return Some(character);
+            }
+        }
+    }
+    None
+}
+
+// window_create(title, length, width, height): returns a window number for
+// the other window calls.
+fn sys_window_create([pointer, length, width, height, _]: [u64; 5]) -> u64 {
+    let title = match user_string(pointer, length) {
+        Ok(title) => title,
+        Err(error) => return error,
+    };
+    if config::get_current_ui_mode() != UiMode::Windowed
+        || !(3..=80).contains(&width)
+        || !(3..=25).contains(&height)
+    {
+        return ERR_INVALID_ARGUMENT;
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    let (x, y) = ((SCREEN_WIDTH - width) / 2, (SCREEN_HEIGHT - height) / 2);
+    let window = Window::new(x, y, width, height, &title, Color::LightGreen);
+    // Added with the process list held, so the window can't outlive it.
+    let index = process::with_current(|process| {
+        let handle = WINDOW_MANAGER.lock().add_window(window)?;
+        process.windows.push(handle);
+        Some(process.windows.len() as u64 - 1)
+    });
+    match index {
+        Some(Some(index)) => index,
+        Some(None) => ERR_NO_MEMORY,
+        None => ERR_INVALID_ARGUMENT,
+    }
+}
+
+// window_print(window, x, y, text, length)
+fn sys_window_print([window, x, y, pointer, length]: [u64; 5]) -> u64 {
+    let text = match user_string(pointer, length) {
+        Ok(text) => text,
+        Err(error) => return error,
+    };
+    let handle = match process::with_current(|process| process.windows.get(window as usize).copied()) {
+        Some(Some(handle)) => handle,
+        _ => return ERR_INVALID_ARGUMENT,
+    };
+    // Positions are inside the border, which the process may not draw over.
+    let (width, height) = match handle.size() {
+        Some((width, height)) => (width as u64, height as u64),
+        None => return ERR_INVALID_ARGUMENT,
+    };
+    if x >= width.saturating_sub(2) || y >= height.saturating_sub(2) {
+        return ERR_INVALID_ARGUMENT;
+    }
+    handle.print_at(x as usize, y as usize, &text);
+    0
+}
+
+// sleep(ticks): at most MAX_SLEEP_TICKS.
+fn sys_sleep([ticks, ..]: [u64; 5]) -> u64 {
+    if ticks > MAX_SLEEP_TICKS {
+        return ERR_INVALID_ARGUMENT;
+    }
+    thread::sleep(ticks);
+    0
+}
+
+// exit(status)
+fn sys_exit(_: [u64; 5]) -> u64 {
+    process::exit_current();
+}
+
+// map(size): maps zeroed, writable memory and returns its address.
+fn sys_map([size, ..]: [u64; 5]) -> u64 {
+    if size == 0 {
+        return ERR_INVALID_ARGUMENT;
+    }
+    let start = match process::with_current(|process| process::reserve_map(process, size)) {
+        Some(Some(start)) => start,
+        _ => return ERR_NO_MEMORY,
+    };
+    // The caller's page tables are the active ones.
+    match memory::map_active_user(VirtAddr::new(start), size) {
+        Some(()) => start,
+        None => {
+            process::with_current(|process| process::cancel_map(process, start));
+            ERR_NO_MEMORY
+        },
+    }
+}
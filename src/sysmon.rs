@@ -0,0 +1,86 @@
+use crate::allocator;
+use crate::time;
+use crate::vga::Color;
+use crate::widgets::{self, Form, WidgetKind};
+use crate::window_manager::{Window, WindowHandle, WINDOW_MANAGER};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const HEAP_BAR: usize = 1;
+const UPTIME: usize = 2;
+const WINDOW_LIST: usize = 4;
+const HIDE_MINIMIZED: usize = 5;
+const TITLE_FIELD: usize = 7;
+
+fn windows(form: &Form) -> Vec<(WindowHandle, String)> {
+    let hide_minimized = form.checked(HIDE_MINIMIZED);
+    WINDOW_MANAGER
+        .lock()
+        .windows()
+        .filter(|(_, window)| !(hide_minimized && window.minimized))
+        .map(|(handle, window)| (handle, format!("{:>2} {}", handle.id(), window.title)))
+        .collect()
+}
+
+fn selected_window(form: &Form) -> Option<WindowHandle> {
+    let index = form.selected(WINDOW_LIST)?;
+    windows(form).get(index).map(|(handle, _)| *handle)
+}
+
+fn refresh(form: &mut Form) {
+    let (used, size) = allocator::heap_usage();
+    form.set_progress(HEAP_BAR, used, size);
+
+    let seconds = time::uptime_seconds();
+    let uptime = format!("Uptime: {:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60);
+    form.set_text(UPTIME, &uptime);
+
+    let items = windows(form).into_iter().map(|(_, title)| title).collect();
+    form.set_items(WINDOW_LIST, items);
+}
+
+pub fn open() -> Option<WindowHandle> {
+    let window = WINDOW_MANAGER
+        .lock()
+        .add_window(Window::new(44, 1, 34, 16, "System Monitor", Color::LightGreen))?;
+
+    let mut form = Form::new(window);
+    form.add(0, 0, WidgetKind::label("Heap"));
+    form.add(6, 0, WidgetKind::progress_bar(25));
+    form.add(0, 1, WidgetKind::label(""));
+    form.add(0, 3, WidgetKind::label("Windows"));
+    form.add(0, 4, WidgetKind::list(Vec::new(), 31, 5));
+    form.add(0, 9, WidgetKind::checkbox("Hide minimised", false));
+    form.add(0, 10, WidgetKind::label("Title"));
+    form.add(6, 10, WidgetKind::text_field(25));
+    let refresh_button = form.add(0, 12, WidgetKind::button("Refresh"));
+    let focus_button = form.add(12, 12, WidgetKind::button("Focus"));
+    let close_button = form.add(22, 12, WidgetKind::button("Close"));
+
+    form.on_activate(TITLE_FIELD, Box::new(|form, _| {
+        if let (Some(handle), Some(title)) = (selected_window(form), form.text(TITLE_FIELD)) {
+            handle.set_title(title);
+        }
+        form.set_text(TITLE_FIELD, "");
+        refresh(form);
+    }));
+    form.on_activate(HIDE_MINIMIZED, Box::new(|form, _| refresh(form)));
+    form.on_activate(refresh_button, Box::new(|form, _| refresh(form)));
+    form.on_activate(focus_button, Box::new(|form, _| {
+        if let Some(handle) = selected_window(form) {
+            WINDOW_MANAGER.lock().set_active_window(handle);
+        }
+    }));
+    form.on_activate(close_button, Box::new(|form, _| {
+        if let Some(handle) = selected_window(form) {
+            crate::terminal::close_window(handle);
+        }
+        refresh(form);
+    }));
+
+    refresh(&mut form);
+    widgets::open(form);
+    Some(window)
+}
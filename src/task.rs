@@ -0,0 +1,204 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::future::{poll_fn, Future};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use crossbeam_queue::ArrayQueue;
+use lazy_static::lazy_static;
+use crate::sync::{IrqMutex, Mutex};
+use x86_64::instructions::interrupts;
+
+const TASK_QUEUE_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+pub struct Task {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+impl Task {
+    pub fn new(future: impl Future<Output = ()> + Send + 'static) -> Self {
+        Task {
+            id: TaskId::new(),
+            future: Box::pin(future),
+        }
+    }
+
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
+
+lazy_static! {
+    // Tasks spawned by other tasks, picked up by the executor on its next pass.
+    static ref SPAWNED: Mutex<Vec<Task>> = Mutex::new(Vec::new());
+}
+
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+    SPAWNED.lock().push(Task::new(future));
+}
+
+struct TaskWaker {
+    task_id: TaskId,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+}
+
+impl TaskWaker {
+    fn waker(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+        Waker::from(Arc::new(TaskWaker { task_id, task_queue }))
+    }
+
+    fn wake_task(&self) {
+        // The task is already queued if this fails.
+        let _ = self.task_queue.push(self.task_id);
+    }
+}
+
+// Waking only touches the lock-free queue, so interrupt handlers can wake tasks.
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    waker_cache: BTreeMap<TaskId, Waker>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(ArrayQueue::new(TASK_QUEUE_SIZE)),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, task: Task) {
+        let task_id = task.id;
+        if self.tasks.insert(task_id, task).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+        self.task_queue.push(task_id).expect("task queue full");
+    }
+
+    pub fn run(&mut self) -> ! {
+        loop {
+            let spawned = core::mem::take(&mut *SPAWNED.lock());
+            for task in spawned {
+                self.spawn(task);
+            }
+            crate::time::wake_sleepers();
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    fn run_ready_tasks(&mut self) {
+        while let Some(task_id) = self.task_queue.pop() {
+            let task = match self.tasks.get_mut(&task_id) {
+                Some(task) => task,
+                None => continue,
+            };
+            let waker = self
+                .waker_cache
+                .entry(task_id)
+                .or_insert_with(|| TaskWaker::waker(task_id, self.task_queue.clone()));
+            let mut context = Context::from_waker(waker);
+            if task.poll(&mut context).is_ready() {
+                self.tasks.remove(&task_id);
+                self.waker_cache.remove(&task_id);
+            }
+        }
+    }
+
+    // Interrupts are off while checking, so a wakeup arriving just before the
+    // `hlt` still ends it.
+    fn sleep_if_idle(&self) {
+        interrupts::disable();
+        // A preempted thread may hold the spawn or sleeper lock; treat that as busy.
+        let idle = self.task_queue.is_empty()
+            && SPAWNED.try_lock().is_some_and(|spawned| spawned.is_empty())
+            && !crate::time::sleeper_due();
+        if !idle {
+            interrupts::enable();
+        } else if crate::thread::others_runnable() {
+            // Halting would also stall the other threads until the next tick.
+            interrupts::enable();
+            crate::thread::yield_now();
+        } else {
+            interrupts::enable_and_hlt();
+        }
+    }
+}
+
+// Holds the waker of the one task waiting on an interrupt-fed queue.
+pub struct WakerSlot {
+    waker: IrqMutex<Option<Waker>>,
+}
+
+impl WakerSlot {
+    pub const fn new() -> Self {
+        WakerSlot { waker: IrqMutex::new(None) }
+    }
+
+    fn register(&self, waker: &Waker) {
+        // The old waker may free its task's waker when dropped, so that
+        // happens after the lock is released.
+        let previous = self.waker.lock().replace(waker.clone());
+        drop(previous);
+    }
+
+    // Called from interrupt handlers; waking only pushes to a queue.
+    pub fn wake(&self) {
+        if let Some(waker) = self.waker.lock().as_ref() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+// Items an interrupt handler pushes to a queue, read by a single task.
+pub struct QueueStream<T: 'static> {
+    queue: &'static ArrayQueue<T>,
+    waker: &'static WakerSlot,
+}
+
+impl<T> QueueStream<T> {
+    pub fn new(queue: &'static ArrayQueue<T>, waker: &'static WakerSlot) -> Self {
+        QueueStream { queue, waker }
+    }
+
+    fn poll_next(&mut self, context: &mut Context) -> Poll<T> {
+        if let Some(item) = self.queue.pop() {
+            return Poll::Ready(item);
+        }
+        self.waker.register(context.waker());
+        match self.queue.pop() {
+            Some(item) => Poll::Ready(item),
+            None => Poll::Pending,
+        }
+    }
+
+    pub async fn next(&mut self) -> T {
+        poll_fn(|context| self.poll_next(context)).await
+    }
+}
@@ -1,14 +1,37 @@
-use crate::window_manager::Window;
-use crate::vga::{Color, WRITER, ColorCode};
-use spin::Mutex;
+use crate::config::{self, UiMode};
+use crate::events::Event;
+use crate::keyboard::KeyEvent;
+use crate::window_manager::{self, Window, WindowHandle, WINDOW_MANAGER};
+use crate::vga::{self, Color, WRITER, ColorCode};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use crate::sync::Mutex;
 use lazy_static::lazy_static;
+use pc_keyboard::KeyCode;
 
 const MAX_HISTORY: usize = 10;
 const MAX_HISTORY_LINES: usize = 100;
+const DEFAULT_PS1: &str = "$ ";
+const MAX_TERMINAL_WINDOWS: usize = 4;
+const MAX_SCROLLBACK: usize = 100;
 
 pub enum TerminalOutput {
-    Windowed(Window),
-    Fullscreen,
+    Windowed(WindowHandle),
+    Fullscreen(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TerminalBinding {
+    Window(WindowHandle),
+    Console(usize),
+}
+
+#[derive(Clone)]
+pub struct Variable {
+    pub value: String,
+    pub exported: bool,
 }
 
 pub struct Terminal {
@@ -16,109 +39,147 @@ pub struct Terminal {
     history: [Option<[char; 64]>; MAX_HISTORY],
     history_lines: [[char; 64]; MAX_HISTORY_LINES],
     history_index: usize,
+    input: [char; 64],
+    input_pos: usize,
     current_line: usize,
-    prompt: &'static str,
+    column: usize,
+    input_column: usize,
+    window_size: Option<(usize, usize)>,
+    // Output as logical (unwrapped) lines, replayed when the window is resized.
+    scrollback: Vec<String>,
+    line: String,
+    pub capture: Option<String>,
+    pub stdin: Option<String>,
+    pub vars: BTreeMap<String, Variable>,
+    pub cwd: String,
+    pub last_status: i32,
+    pub args: Vec<String>,
+    pub script_depth: usize,
+    pub exit_status: Option<i32>,
 }
 
 impl Terminal {
-    pub fn new_windowed(window: Window) -> Self {
-        let prompt = "$ ";
-        let mut terminal = Terminal {
-            output: TerminalOutput::Windowed(window),
-            history: [None; MAX_HISTORY],
-            history_lines: [['\0'; 64]; MAX_HISTORY_LINES],
-            history_index: 0,
-            current_line: 0,
-            prompt,
+    fn new(output: TerminalOutput) -> Self {
+        let window_size = match &output {
+            TerminalOutput::Windowed(window) => window.size(),
+            TerminalOutput::Fullscreen(_) => None,
         };
+        let mut vars = BTreeMap::new();
+        vars.insert(String::from("PS1"), Variable { value: String::from(DEFAULT_PS1), exported: false });
         
-        terminal.print_at(1, 1, prompt);
-        terminal
-    }
-    
-    pub fn new_fullscreen() -> Self {
-        WRITER.lock().clear_screen();
-        
-        let prompt = "$ ";
-        let mut terminal = Terminal {
-            output: TerminalOutput::Fullscreen,
+        Terminal {
+            output,
             history: [None; MAX_HISTORY],
             history_lines: [['\0'; 64]; MAX_HISTORY_LINES],
             history_index: 0,
+            input: ['\0'; 64],
+            input_pos: 0,
             current_line: 0,
-            prompt,
-        };
+            column: 0,
+            input_column: 0,
+            window_size,
+            scrollback: Vec::new(),
+            line: String::new(),
+            capture: None,
+            stdin: None,
+            vars,
+            cwd: String::from("/"),
+            last_status: 0,
+            args: Vec::new(),
+            script_depth: 0,
+            exit_status: None,
+        }
+    }
+    
+    pub fn new_windowed(window: WindowHandle) -> Self {
+        Terminal::new(TerminalOutput::Windowed(window))
+    }
+    
+    pub fn new_fullscreen(console: usize) -> Self {
+        WRITER.lock().console_clear(console);
         
-        terminal.print_at(1, 0, prompt);
-        terminal
+        Terminal::new(TerminalOutput::Fullscreen(console))
     }
     
-    pub fn handle_input(&mut self, c: char, buffer: &mut [char; 64], buffer_pos: &mut usize) {
+    pub fn inherit_env(&mut self, parent: &Terminal) {
+        for (name, var) in parent.vars.iter().filter(|(_, var)| var.exported) {
+            self.vars.insert(name.clone(), var.clone());
+        }
+        self.cwd = parent.cwd.clone();
+    }
+    
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if key.pressed && key.modifiers.ctrl && key.code == KeyCode::L {
+            self.clear_screen();
+        } else if let Some(c) = key.typed_char() {
+            self.handle_input(c);
+        }
+    }
+    
+    pub fn handle_input(&mut self, c: char) {
         match c {
-            '\n' => self.process_command(buffer, buffer_pos),
-            '\u{0008}' => self.handle_backspace(buffer, buffer_pos),
-            _ => self.handle_character(c, buffer, buffer_pos),
+            '\n' => self.process_command(),
+            '\u{0008}' => self.handle_backspace(),
+            _ => self.handle_character(c),
         }
     }
     
-    fn handle_character(&mut self, c: char, buffer: &mut [char; 64], buffer_pos: &mut usize) {
-        if *buffer_pos < buffer.len() - 1 {
-            buffer[*buffer_pos] = c;
-            *buffer_pos += 1;
+    fn handle_character(&mut self, c: char) {
+        if self.input_pos < self.input.len() - 1 {
+            self.input[self.input_pos] = c;
+            self.input_pos += 1;
+            self.line.push(c);
             
-            let c_str = [c as u8];
+            let mut c_str = [0; 4];
             self.print_at(
-                1 + self.prompt.len() + *buffer_pos - 1,
+                1 + self.input_column + self.input_pos - 1,
                 self.current_line,
-                core::str::from_utf8(&c_str).unwrap_or("")
+                c.encode_utf8(&mut c_str)
             );
         }
     }
     
-    fn handle_backspace(&mut self, buffer: &mut [char; 64], buffer_pos: &mut usize) {
-        if *buffer_pos > 0 {
-            *buffer_pos -= 1;
-            buffer[*buffer_pos] = '\0';
+    fn handle_backspace(&mut self) {
+        if self.input_pos > 0 {
+            self.input_pos -= 1;
+            self.input[self.input_pos] = '\0';
+            self.line.pop();
             
             self.print_at(
-                1 + self.prompt.len() + *buffer_pos,
+                1 + self.input_column + self.input_pos,
                 self.current_line,
                 " "
             );
         }
     }
     
-    fn process_command(&mut self, buffer: &mut [char; 64], buffer_pos: &mut usize) {
-        let mut command_str = [0u8; 64];
-        for (i, &ch) in buffer[0..*buffer_pos].iter().enumerate() {
-            command_str[i] = ch as u8;
-        }
-        let command = core::str::from_utf8(&command_str[..*buffer_pos]).unwrap_or("");
+    fn process_command(&mut self) {
+        let command: String = self.input[..self.input_pos].iter().collect();
         
-        if *buffer_pos > 0 {
-            let mut history_entry = ['\0'; 64];
-            for (i, &ch) in buffer[0..*buffer_pos].iter().enumerate() {
-                history_entry[i] = ch;
-            }
-            self.add_to_history(history_entry);
+        if self.input_pos > 0 {
+            self.add_to_history(self.input);
         }
         
-        for i in 0..buffer.len() {
-            buffer[i] = '\0';
-        }
-        *buffer_pos = 0;
+        self.input = ['\0'; 64];
+        self.input_pos = 0;
         
-        self.current_line += 1;
-        let response = self.execute_command(command);
+        self.new_line();
+        self.run_source(&command);
         
-        if !response.is_empty() {
-            self.print_at(1, self.current_line, response);
-            self.current_line += 1;
+        if self.column > 0 {
+            self.new_line();
         }
         
-        self.scroll_if_needed();
-        
-        self.print_at(1, self.current_line, self.prompt);
+        // The command may have opened or closed windows that retiled the others.
+        self.sync_size();
+        window_manager::dispatch_events();
+        self.show_prompt();
+    }
+    
+    pub fn show_prompt(&mut self) {
+        let prompt = self.render_prompt();
+        let _ = fmt::Write::write_str(self, &prompt);
+        self.input_column = self.column;
     }
     
     fn add_to_history(&mut self, entry: [char; 64]) {
@@ -133,9 +194,89 @@ impl Terminal {
     pub fn clear(&mut self) {
         match &self.output {
             TerminalOutput::Windowed(window) => window.clear(),
-            TerminalOutput::Fullscreen => WRITER.lock().clear_screen(),
+            TerminalOutput::Fullscreen(console) => WRITER.lock().console_clear(*console),
+        }
+        self.current_line = 0;
+        self.column = 0;
+        self.scrollback.clear();
+        self.line.clear();
+    }
+    
+    // Ctrl+L: clears the screen but keeps the command being typed.
+    fn clear_screen(&mut self) {
+        let input: String = self.input[..self.input_pos].iter().collect();
+        self.clear();
+        self.show_prompt();
+        let _ = fmt::Write::write_str(self, &input);
+    }
+    
+    // Re-lays out the scrollback for the window's new size.
+    pub fn reflow(&mut self) {
+        if let TerminalOutput::Windowed(window) = &self.output {
+            window.clear();
         }
         self.current_line = 0;
+        self.column = 0;
+        
+        let visible = self.output_height();
+        let start = self.scrollback.len().saturating_sub(visible);
+        let mut lines: Vec<String> = self.scrollback[start..].to_vec();
+        lines.push(self.line.clone());
+        
+        window_manager::batch(|| {
+            for (i, line) in lines.iter().enumerate() {
+                if i > 0 {
+                    self.advance_line();
+                }
+                for c in line.chars() {
+                    self.put_char(c);
+                }
+            }
+        });
+        self.input_column = self.column.saturating_sub(self.input_pos);
+    }
+    
+    fn sync_size(&mut self) {
+        if let TerminalOutput::Windowed(window) = &self.output {
+            let size = window.size();
+            if size != self.window_size {
+                self.window_size = size;
+                self.reflow();
+            }
+        }
+    }
+    
+    pub fn history(&self) -> impl Iterator<Item = &[char; 64]> {
+        self.history.iter().flatten()
+    }
+    
+    fn output_width(&self) -> usize {
+        match &self.output {
+            TerminalOutput::Windowed(window) => window.size().map_or(1, |(width, _)| width.saturating_sub(3).max(1)),
+            TerminalOutput::Fullscreen(_) => 79,
+        }
+    }
+    
+    fn output_height(&self) -> usize {
+        match &self.output {
+            TerminalOutput::Windowed(window) => window.size().map_or(1, |(_, height)| height.saturating_sub(2).max(1)),
+            TerminalOutput::Fullscreen(_) => 25,
+        }
+    }
+    
+    fn new_line(&mut self) {
+        let line = core::mem::take(&mut self.line);
+        if self.scrollback.len() >= MAX_SCROLLBACK {
+            self.scrollback.remove(0);
+        }
+        self.scrollback.push(line);
+        self.advance_line();
+    }
+    
+    fn advance_line(&mut self) {
+        self.current_line += 1;
+        self.column = 0;
+        self.scroll_if_needed();
     }
     
     fn print_at(&mut self, x_offset: usize, y_offset: usize, text: &str) {
@@ -143,14 +284,14 @@ impl Terminal {
             TerminalOutput::Windowed(window) => {
                 window.print_at(x_offset, y_offset, text);
             },
-            TerminalOutput::Fullscreen => {
+            TerminalOutput::Fullscreen(console) => {
                 let mut writer = WRITER.lock();
                 let original_color = writer.color_code;
                 writer.color_code = ColorCode::new(Color::White, Color::Black);
                 
-                for (i, &byte) in text.as_bytes().iter().enumerate() {
+                for (i, byte) in text.chars().map(vga::cp437).enumerate() {
                     let x = x_offset + i;
-                    writer.write_char_at(x, y_offset, byte);
+                    writer.console_write_char_at(*console, x, y_offset, byte);
                 }
                 
                 writer.color_code = original_color;
@@ -159,28 +300,273 @@ impl Terminal {
     }
     
     fn scroll_if_needed(&mut self) {
-        let max_height = match &self.output {
-            TerminalOutput::Windowed(window) => window.height - 2,
-            TerminalOutput::Fullscreen => 25,
-        };
+        let max_height = self.output_height();
+        
+        while self.current_line >= max_height {
+            match &self.output {
+                TerminalOutput::Windowed(window) => window.scroll_up(),
+                TerminalOutput::Fullscreen(console) => {
+                    let mut writer = WRITER.lock();
+                    let original_color = writer.color_code;
+                    writer.color_code = ColorCode::new(Color::White, Color::Black);
+                    writer.console_scroll_region(*console, 0, 0, 80, max_height);
+                    writer.color_code = original_color;
+                }
+            }
+            self.current_line -= 1;
+        }
+    }
+}
+
+impl fmt::Write for Terminal {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if let Some(capture) = self.capture.as_mut() {
+            capture.push_str(s);
+            return Ok(());
+        }
         
-        if self.current_line >= max_height {
-            self.clear();
-            self.current_line = 0;
+        for c in s.chars() {
+            if c == '\n' {
+                self.new_line();
+                continue;
+            }
+            
+            self.line.push(c);
+            self.put_char(c);
         }
+        Ok(())
+    }
+}
+
+impl Terminal {
+    pub fn binding(&self) -> TerminalBinding {
+        match self.output {
+            TerminalOutput::Windowed(window) => TerminalBinding::Window(window),
+            TerminalOutput::Fullscreen(console) => TerminalBinding::Console(console),
+        }
+    }
+
+    fn put_char(&mut self, c: char) {
+        if self.column >= self.output_width() {
+            self.advance_line();
+        }
+        
+        let mut c_str = [0; 4];
+        self.print_at(1 + self.column, self.current_line, c.encode_utf8(&mut c_str));
+        self.column += 1;
+    }
+}
+
+struct TerminalSlot {
+    id: usize,
+    binding: TerminalBinding,
+    terminal: Option<Terminal>,
+}
+
+pub struct TerminalSet {
+    slots: Vec<TerminalSlot>,
+    next_id: usize,
+}
+
+impl TerminalSet {
+    pub fn new() -> Self {
+        TerminalSet {
+            slots: Vec::new(),
+            next_id: 0,
+        }
+    }
+    
+    fn insert(&mut self, binding: TerminalBinding, terminal: Terminal) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.slots.push(TerminalSlot { id, binding, terminal: Some(terminal) });
+        id
+    }
+    
+    fn find(&self, binding: TerminalBinding) -> Option<usize> {
+        self.slots.iter().position(|slot| slot.binding == binding)
+    }
+    
+    // Terminals are checked out while they handle input so that commands can
+    // open further terminals or query the set without deadlocking on it.
+    fn check_out(&mut self, binding: TerminalBinding) -> Option<(usize, Terminal)> {
+        let slot = self.slots.iter_mut().find(|slot| slot.binding == binding)?;
+        slot.terminal.take().map(|terminal| (slot.id, terminal))
+    }
+    
+    fn check_in(&mut self, id: usize, terminal: Terminal) {
+        if let Some(slot) = self.slots.iter_mut().find(|slot| slot.id == id) {
+            slot.terminal = Some(terminal);
+        }
+    }
+    
+    pub fn bindings(&self) -> impl Iterator<Item = TerminalBinding> + '_ {
+        self.slots.iter().map(|slot| slot.binding)
     }
 }
 
 lazy_static! {
-    pub static ref TERMINAL: Mutex<Option<Terminal>> = Mutex::new(None);
+    pub static ref TERMINALS: Mutex<TerminalSet> = Mutex::new(TerminalSet::new());
+    // Output from user processes, waiting until the terminal that started them is free.
+    static ref PROCESS_OUTPUT: Mutex<Vec<(TerminalBinding, String)>> = Mutex::new(Vec::new());
 }
 
-pub fn init_terminal_windowed(window: Window) {
-    let terminal = Terminal::new_windowed(window);
-    *TERMINAL.lock() = Some(terminal);
+fn focused_binding() -> Option<TerminalBinding> {
+    match config::get_current_ui_mode() {
+        UiMode::Windowed => WINDOW_MANAGER.lock().active_window().map(TerminalBinding::Window),
+        UiMode::FullscreenTerminal => Some(TerminalBinding::Console(WRITER.lock().active_console())),
+    }
+}
+
+pub fn handle_key(key: KeyEvent) {
+    let binding = match focused_binding() {
+        Some(binding) => binding,
+        None => return,
+    };
+    
+    let checked_out = TERMINALS.lock().check_out(binding);
+    if let Some((id, mut terminal)) = checked_out {
+        terminal.handle_key(key);
+        TERMINALS.lock().check_in(id, terminal);
+    }
+    flush_process_output();
+}
+
+pub fn init_terminal_windowed(window: WindowHandle, boot_script: Option<&str>) {
+    *TERMINALS.lock() = TerminalSet::new();
+    
+    let mut terminal = Terminal::new_windowed(window);
+    if let Some(path) = boot_script {
+        terminal.execute_command(&["source", path]);
+        if terminal.column > 0 {
+            terminal.new_line();
+        }
+    }
+    terminal.show_prompt();
+    TERMINALS.lock().insert(TerminalBinding::Window(window), terminal);
 }
 
 pub fn init_terminal_fullscreen() {
-    let terminal = Terminal::new_fullscreen();
-    *TERMINAL.lock() = Some(terminal);
+    *TERMINALS.lock() = TerminalSet::new();
+    WRITER.lock().switch_console(0);
+    
+    let mut terminal = Terminal::new_fullscreen(0);
+    terminal.show_prompt();
+    TERMINALS.lock().insert(TerminalBinding::Console(0), terminal);
+}
+
+pub fn open_terminal_window(parent: Option<&Terminal>) -> Option<WindowHandle> {
+    let count = TERMINALS
+        .lock()
+        .bindings()
+        .filter(|binding| matches!(binding, TerminalBinding::Window(_)))
+        .count();
+    if count >= MAX_TERMINAL_WINDOWS {
+        return None;
+    }
+    
+    let window = Window::new(2 + count * 4, 2 + count * 2, 44, 10, "Terminal", Color::White);
+    let window = WINDOW_MANAGER.lock().add_window(window)?;
+    
+    let mut terminal = Terminal::new_windowed(window);
+    if let Some(parent) = parent {
+        terminal.inherit_env(parent);
+    }
+    terminal.show_prompt();
+    TERMINALS.lock().insert(TerminalBinding::Window(window), terminal);
+    Some(window)
+}
+
+pub fn switch_console(console: usize) {
+    if config::get_current_ui_mode() != UiMode::FullscreenTerminal || console >= vga::CONSOLE_COUNT {
+        return;
+    }
+    
+    let previous = WRITER.lock().active_console();
+    WRITER.lock().switch_console(console);
+    
+    let mut terminals = TERMINALS.lock();
+    if terminals.find(TerminalBinding::Console(console)).is_some() {
+        return;
+    }
+    
+    let mut terminal = Terminal::new_fullscreen(console);
+    if let Some(parent) = terminals
+        .find(TerminalBinding::Console(previous))
+        .and_then(|i| terminals.slots[i].terminal.as_ref())
+    {
+        terminal.inherit_env(parent);
+    }
+    terminal.show_prompt();
+    terminals.insert(TerminalBinding::Console(console), terminal);
+}
+
+// Feeds each windowed terminal the events queued for its window. A terminal
+// that is already checked out, such as the one running the current command,
+// picks up its events on the next dispatch.
+pub fn process_events() {
+    let bindings: Vec<TerminalBinding> = TERMINALS.lock().bindings().collect();
+    for binding in bindings {
+        let window = match binding {
+            TerminalBinding::Window(window) => window,
+            TerminalBinding::Console(_) => continue,
+        };
+        let checked_out = TERMINALS.lock().check_out(binding);
+        if let Some((id, mut terminal)) = checked_out {
+            while let Some(event) = window.poll_event() {
+                match event {
+                    Event::Key(key) => terminal.handle_key(key),
+                    Event::Resized(..) => terminal.sync_size(),
+                    _ => {},
+                }
+            }
+            TERMINALS.lock().check_in(id, terminal);
+        }
+    }
+    flush_process_output();
+}
+
+// Called from process threads; the output is printed on the main thread.
+pub fn write_process_output(binding: TerminalBinding, text: &str) {
+    PROCESS_OUTPUT.lock().push((binding, String::from(text)));
+    crate::task::spawn(async { flush_process_output() });
+}
+
+// Output for a terminal that is busy, such as the one running the command
+// that started the process, stays queued for the next flush.
+fn flush_process_output() {
+    let pending = core::mem::take(&mut *PROCESS_OUTPUT.lock());
+    let mut waiting = Vec::new();
+    for (binding, text) in pending {
+        let checked_out = TERMINALS.lock().check_out(binding);
+        match checked_out {
+            Some((id, mut terminal)) => {
+                let _ = fmt::Write::write_str(&mut terminal, &text);
+                TERMINALS.lock().check_in(id, terminal);
+            },
+            None if TERMINALS.lock().find(binding).is_some() => waiting.push((binding, text)),
+            None => {},
+        }
+    }
+    if !waiting.is_empty() {
+        PROCESS_OUTPUT.lock().splice(0..0, waiting);
+    }
+}
+
+// Closes a window along with its terminal, keeping at least one terminal window open.
+pub fn close_window(window: WindowHandle) -> bool {
+    let mut terminals = TERMINALS.lock();
+    if let Some(i) = terminals.find(TerminalBinding::Window(window)) {
+        let terminal_windows = terminals
+            .bindings()
+            .filter(|binding| matches!(binding, TerminalBinding::Window(_)))
+            .count();
+        if terminal_windows <= 1 {
+            return false;
+        }
+        terminals.slots.remove(i);
+    }
+    drop(terminals);
+    
+    WINDOW_MANAGER.lock().remove_window(window).is_some()
 }
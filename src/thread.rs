@@ -0,0 +1,480 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use lazy_static::lazy_static;
+use x86_64::registers::control::Cr3;
+use x86_64::VirtAddr;
+use crate::gdt;
+use crate::memory::{self, AddressSpace};
+use crate::sync::IrqMutex;
+
+pub const MAX_THREADS: usize = 8;
+const STACK_SIZE: usize = 32 * 1024;
+// Number of general purpose registers the switching handlers push.
+const SAVED_REGISTERS: usize = 15;
+const RFLAGS_INTERRUPTS_ENABLED: u64 = 0x202;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    pub fn from_u64(id: u64) -> Self {
+        ThreadId(id)
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+// Threads of equal priority take turns; higher priorities get longer turns
+// rather than starving the others.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+impl Priority {
+    fn quantum(&self) -> u64 {
+        match self {
+            Priority::Low => 1,
+            Priority::Normal => 2,
+            Priority::High => 4,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThreadState {
+    Ready,
+    Running,
+    Sleeping(u64),
+    Joining(ThreadId),
+    Blocked,
+    Finished,
+}
+
+impl ThreadState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThreadState::Ready => "ready",
+            ThreadState::Running => "running",
+            ThreadState::Sleeping(_) => "sleeping",
+            ThreadState::Joining(_) => "joining",
+            ThreadState::Blocked => "blocked",
+            ThreadState::Finished => "finished",
+        }
+    }
+}
+
+// Copied into a fixed buffer so the scheduler never allocates for it.
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadName {
+    bytes: [u8; 16],
+    len: usize,
+}
+
+impl ThreadName {
+    pub fn new(name: &str) -> Self {
+        let mut len = name.len().min(16);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut bytes = [0; 16];
+        bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+        ThreadName { bytes, len }
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: ThreadName,
+    pub priority: Priority,
+    pub state: ThreadState,
+    pub ticks: u64,
+    // Runs a user program in ring 3 rather than kernel code.
+    pub user: bool,
+}
+
+struct Thread {
+    info: ThreadInfo,
+    // Saved stack pointer while the thread is switched out.
+    rsp: u64,
+    // Freed along with the thread. None for the boot thread, which keeps the
+    // bootloader's stack.
+    stack: Option<Box<[u8]>>,
+    entry: Option<Box<dyn FnOnce() + Send>>,
+    // None for kernel threads, which run on the kernel's page tables.
+    address_space: Option<AddressSpace>,
+}
+
+// Nothing in here may allocate or free memory: with interrupts disabled the
+// allocator lock could belong to a thread that never gets to release it.
+struct Scheduler {
+    threads: [Option<Thread>; MAX_THREADS],
+    current: usize,
+    idle: usize,
+    slice_left: u64,
+    next_id: u64,
+}
+
+impl Scheduler {
+    fn new() -> Self {
+        Scheduler {
+            threads: Default::default(),
+            current: 0,
+            idle: 0,
+            slice_left: 0,
+            next_id: 0,
+        }
+    }
+
+    fn insert(&mut self, thread: Thread) -> Result<ThreadId, Thread> {
+        let slot = match self.threads.iter().position(|slot| slot.is_none()) {
+            Some(slot) => slot,
+            None => return Err(thread),
+        };
+        let id = thread.info.id;
+        self.threads[slot] = Some(thread);
+        Ok(id)
+    }
+
+    fn new_id(&mut self) -> ThreadId {
+        self.next_id += 1;
+        ThreadId(self.next_id - 1)
+    }
+
+    fn current_mut(&mut self) -> &mut Thread {
+        self.threads[self.current].as_mut().expect("current thread missing")
+    }
+
+    fn is_finished(&self, id: ThreadId) -> bool {
+        !self.threads.iter().flatten().any(|thread| thread.info.id == id && thread.info.state != ThreadState::Finished)
+    }
+
+    fn runnable(&self, slot: usize) -> bool {
+        let thread = match &self.threads[slot] {
+            Some(thread) => thread,
+            None => return false,
+        };
+        match thread.info.state {
+            ThreadState::Ready | ThreadState::Running => true,
+            ThreadState::Sleeping(until) => crate::time::ticks() >= until,
+            ThreadState::Joining(id) => self.is_finished(id),
+            ThreadState::Blocked | ThreadState::Finished => false,
+        }
+    }
+
+    // The next runnable thread after the current one, or the idle thread.
+    fn next_runnable(&self) -> usize {
+        (1..=MAX_THREADS)
+            .map(|offset| (self.current + offset) % MAX_THREADS)
+            .find(|&slot| slot != self.idle && self.runnable(slot))
+            .unwrap_or(self.idle)
+    }
+
+    fn others_runnable(&self) -> bool {
+        (0..MAX_THREADS).any(|slot| slot != self.current && slot != self.idle && self.runnable(slot))
+    }
+
+    fn switch(&mut self, rsp: u64, tick: bool) -> u64 {
+        let current = self.current;
+        let thread = self.current_mut();
+        thread.rsp = rsp;
+        if tick {
+            thread.info.ticks += 1;
+        }
+        if thread.info.state == ThreadState::Running {
+            thread.info.state = ThreadState::Ready;
+        }
+
+        if tick {
+            self.slice_left = self.slice_left.saturating_sub(1);
+            if self.slice_left > 0 && self.runnable(current) {
+                self.current_mut().info.state = ThreadState::Running;
+                return rsp;
+            }
+        }
+
+        self.current = self.next_runnable();
+        let thread = self.current_mut();
+        thread.info.state = ThreadState::Running;
+        if let Some(stack) = &thread.stack {
+            let top = VirtAddr::new(stack_top(stack));
+            gdt::set_kernel_stack(top);
+            crate::syscall::set_kernel_stack(top);
+        }
+        let level_4_frame = match &thread.address_space {
+            Some(address_space) => address_space.level_4_frame(),
+            None => memory::kernel_level_4_table(),
+        };
+        let (rsp, quantum) = (thread.rsp, thread.info.priority.quantum());
+        self.slice_left = quantum;
+
+        let (active_frame, flags) = Cr3::read();
+        if active_frame != level_4_frame {
+            unsafe { Cr3::write(level_4_frame, flags) };
+        }
+        rsp
+    }
+
+    // Takes finished threads out so their stacks can be freed once the lock is released.
+    fn reap(&mut self) -> [Option<Thread>; MAX_THREADS] {
+        let mut finished: [Option<Thread>; MAX_THREADS] = Default::default();
+        for (slot, reaped) in self.threads.iter_mut().zip(finished.iter_mut()) {
+            if slot.as_ref().is_some_and(|thread| thread.info.state == ThreadState::Finished) {
+                *reaped = slot.take();
+            }
+        }
+        // Threads joining a reaped one have nothing left to wait for.
+        for thread in self.threads.iter_mut().flatten() {
+            if let ThreadState::Joining(id) = thread.info.state {
+                if finished.iter().flatten().any(|reaped| reaped.info.id == id) {
+                    thread.info.state = ThreadState::Ready;
+                }
+            }
+        }
+        finished
+    }
+}
+
+lazy_static! {
+    static ref SCHEDULER: IrqMutex<Scheduler> = IrqMutex::new(Scheduler::new());
+}
+
+// Registers the running boot code as the first thread and starts the idle
+// thread. Runs before interrupts are enabled.
+pub fn init() {
+    let mut scheduler = SCHEDULER.lock();
+    let id = scheduler.new_id();
+    let boot = Thread {
+        info: ThreadInfo {
+            id,
+            name: ThreadName::new("kernel"),
+            // Runs the executor, so input and the UI get the longest turns.
+            priority: Priority::High,
+            state: ThreadState::Running,
+            ticks: 0,
+            user: false,
+        },
+        rsp: 0,
+        stack: None,
+        entry: None,
+        address_space: None,
+    };
+    let _ = scheduler.insert(boot);
+    scheduler.slice_left = Priority::High.quantum();
+
+    let id = scheduler.new_id();
+    let idle = new_kernel_thread(id, "idle", Priority::Low, Box::new(|| loop {
+        x86_64::instructions::hlt();
+    }));
+    let _ = scheduler.insert(idle);
+    scheduler.idle = 1;
+}
+
+// Called by the timer and yield handlers with the interrupted thread's saved
+// registers on top of its stack; returns the stack to resume instead.
+pub fn switch(rsp: u64, tick: bool) -> u64 {
+    SCHEDULER.lock().switch(rsp, tick)
+}
+
+fn stack_top(stack: &[u8]) -> u64 {
+    (stack.as_ptr() as u64 + stack.len() as u64) & !0xf
+}
+
+// Lays out a new kernel stack as if the thread had been interrupted just
+// before its first instruction, so switching to it is the same as switching
+// back. `frame` gives the interrupt frame (RIP, CS, RFLAGS, RSP and SS) for
+// the stack's top address.
+fn new_thread(info: ThreadInfo, frame: impl FnOnce(u64) -> [u64; 5]) -> Thread {
+    let stack = vec![0u8; STACK_SIZE].into_boxed_slice();
+    let top = stack_top(&stack);
+    let frame = frame(top);
+    let rsp = top - ((SAVED_REGISTERS + frame.len()) * 8) as u64;
+    unsafe {
+        let words = rsp as *mut u64;
+        for i in 0..SAVED_REGISTERS {
+            words.add(i).write(0);
+        }
+        for (i, word) in frame.iter().enumerate() {
+            words.add(SAVED_REGISTERS + i).write(*word);
+        }
+    }
+
+    Thread { info, rsp, stack: Some(stack), entry: None, address_space: None }
+}
+
+fn new_kernel_thread(id: ThreadId, name: &str, priority: Priority, entry: Box<dyn FnOnce() + Send>) -> Thread {
+    let info = ThreadInfo { id, name: ThreadName::new(name), priority, state: ThreadState::Ready, ticks: 0, user: false };
+    let selectors = gdt::selectors();
+    // The entry point sees the stack as if it had been called.
+    let mut thread = new_thread(info, |top| {
+        [
+            thread_start as *const () as u64,
+            u64::from(selectors.kernel_code.0),
+            RFLAGS_INTERRUPTS_ENABLED,
+            top - 8,
+            u64::from(selectors.kernel_data.0),
+        ]
+    });
+    thread.entry = Some(entry);
+    thread
+}
+
+extern "C" fn thread_start() -> ! {
+    let entry = SCHEDULER.lock().current_mut().entry.take();
+    if let Some(entry) = entry {
+        entry();
+    }
+    exit();
+}
+
+// Finished threads, and a new one that doesn't fit, are only freed once
+// interrupts are back on.
+fn reap_and_new_id() -> ThreadId {
+    let (finished, id) = {
+        let mut scheduler = SCHEDULER.lock();
+        (scheduler.reap(), scheduler.new_id())
+    };
+    drop(finished);
+    id
+}
+
+pub fn spawn(name: &str, priority: Priority, f: impl FnOnce() + Send + 'static) -> Option<ThreadId> {
+    let id = reap_and_new_id();
+    let thread = new_kernel_thread(id, name, priority, Box::new(f));
+    let inserted = SCHEDULER.lock().insert(thread);
+    inserted.ok()
+}
+
+// Starts a thread that enters ring 3 at `entry` in the given address space,
+// with `arguments` in rdi, rsi and rdx.
+pub fn spawn_user(
+    name: &str,
+    address_space: AddressSpace,
+    entry: VirtAddr,
+    stack_top: VirtAddr,
+    arguments: [u64; 3],
+) -> Option<ThreadId> {
+    let id = reap_and_new_id();
+    let name = ThreadName::new(name);
+    let info = ThreadInfo { id, name, priority: Priority::Normal, state: ThreadState::Ready, ticks: 0, user: true };
+    let selectors = gdt::selectors();
+    let mut thread = new_thread(info, |_| {
+        [
+            entry.as_u64(),
+            u64::from(selectors.user_code.0),
+            RFLAGS_INTERRUPTS_ENABLED,
+            stack_top.as_u64(),
+            u64::from(selectors.user_data.0),
+        ]
+    });
+    // Saved registers are stored from r15 down to rax; rdi, rsi and rdx sit
+    // at these positions.
+    unsafe {
+        let registers = thread.rsp as *mut u64;
+        for (i, argument) in [9, 10, 11].into_iter().zip(arguments) {
+            registers.add(i).write(argument);
+        }
+    }
+    thread.address_space = Some(address_space);
+    let inserted = SCHEDULER.lock().insert(thread);
+    inserted.ok()
+}
+
+pub fn yield_now() {
+    unsafe { core::arch::asm!("int {}", const crate::interrupts::YIELD_VECTOR) };
+}
+
+pub fn sleep(ticks: u64) {
+    let until = crate::time::ticks().saturating_add(ticks);
+    SCHEDULER.lock().current_mut().info.state = ThreadState::Sleeping(until);
+    yield_now();
+}
+
+// Waits until the thread with the given id has finished.
+#[allow(dead_code)]
+pub fn join(id: ThreadId) {
+    let finished = {
+        let mut scheduler = SCHEDULER.lock();
+        let finished = scheduler.is_finished(id);
+        if !finished {
+            scheduler.current_mut().info.state = ThreadState::Joining(id);
+        }
+        finished
+    };
+    if !finished {
+        yield_now();
+    }
+}
+
+pub fn exit() -> ! {
+    SCHEDULER.lock().current_mut().info.state = ThreadState::Finished;
+    yield_now();
+    unreachable!("finished thread was rescheduled");
+}
+
+// Ends a user thread. Kernel threads may be holding locks, so they can only
+// end themselves.
+pub fn kill(id: ThreadId) -> bool {
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current_mut().info.id;
+    match scheduler.threads.iter_mut().flatten().find(|thread| thread.info.id == id) {
+        Some(thread) if thread.info.user && id != current => {
+            thread.info.state = ThreadState::Finished;
+            true
+        },
+        _ => false,
+    }
+}
+
+pub fn current() -> ThreadId {
+    SCHEDULER.lock().current_mut().info.id
+}
+
+// Marks the current thread as waiting for `unblock`; it keeps running until
+// it yields. Used by the primitives in `sync`.
+pub fn block_current() {
+    SCHEDULER.lock().current_mut().info.state = ThreadState::Blocked;
+}
+
+// False if the thread was no longer waiting, for instance because it was killed.
+pub fn unblock(id: ThreadId) -> bool {
+    let mut scheduler = SCHEDULER.lock();
+    match scheduler.threads.iter_mut().flatten().find(|thread| thread.info.id == id) {
+        Some(thread) if thread.info.state == ThreadState::Blocked => {
+            thread.info.state = ThreadState::Ready;
+            true
+        },
+        _ => false,
+    }
+}
+
+// Whether threads other than the caller and the idle thread could run now.
+pub fn others_runnable() -> bool {
+    SCHEDULER.lock().others_runnable()
+}
+
+pub fn list() -> [Option<ThreadInfo>; MAX_THREADS] {
+    let scheduler = SCHEDULER.lock();
+    let mut threads = [None; MAX_THREADS];
+    for (info, thread) in threads.iter_mut().zip(scheduler.threads.iter()) {
+        *info = thread.as_ref().map(|thread| thread.info);
+    }
+    threads
+}
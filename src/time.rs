@@ -0,0 +1,114 @@
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use lazy_static::lazy_static;
+use crate::sync::Mutex;
+use x86_64::instructions::port::Port;
+
+pub const TICKS_PER_SECOND: u64 = 100;
+
+const PIT_FREQUENCY: u64 = 1_193_182;
+const PIT_COMMAND_PORT: u16 = 0x43;
+const PIT_CHANNEL_0_PORT: u16 = 0x40;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    // Tasks waiting for a tick, woken by the executor rather than the timer
+    // interrupt so dropping their wakers never happens in interrupt context.
+    static ref SLEEPERS: Mutex<Vec<(u64, Waker)>> = Mutex::new(Vec::new());
+}
+
+pub fn init() {
+    let divisor = (PIT_FREQUENCY / TICKS_PER_SECOND) as u16;
+    let mut command: Port<u8> = Port::new(PIT_COMMAND_PORT);
+    let mut channel_0: Port<u8> = Port::new(PIT_CHANNEL_0_PORT);
+
+    unsafe {
+        // Channel 0, lobyte/hibyte access, square wave generator.
+        command.write(0x36);
+        channel_0.write((divisor & 0xff) as u8);
+        channel_0.write((divisor >> 8) as u8);
+    }
+}
+
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn uptime_seconds() -> u64 {
+    ticks() / TICKS_PER_SECOND
+}
+
+pub struct Sleep {
+    until: u64,
+    // Set once the waker is in SLEEPERS. A task's waker doesn't change, so
+    // later polls don't need to add it again.
+    registered: bool,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if ticks() >= self.until {
+            return Poll::Ready(());
+        }
+        if !self.registered {
+            SLEEPERS.lock().push((self.until, context.waker().clone()));
+            self.registered = true;
+        }
+        Poll::Pending
+    }
+}
+
+#[allow(dead_code)]
+pub fn sleep(duration: u64) -> Sleep {
+    sleep_until(ticks() + duration)
+}
+
+pub fn sleep_until(tick: u64) -> Sleep {
+    Sleep { until: tick, registered: false }
+}
+
+pub fn wake_sleepers() {
+    let now = ticks();
+    SLEEPERS.lock().retain(|(until, waker)| {
+        if *until <= now {
+            waker.wake_by_ref();
+        }
+        *until > now
+    });
+}
+
+pub fn sleeper_due() -> bool {
+    let now = ticks();
+    SLEEPERS.try_lock().is_none_or(|sleepers| sleepers.iter().any(|(until, _)| *until <= now))
+}
+
+// Yields the tick count once per tick. Ticks that pass while the task is
+// busy are skipped rather than delivered late.
+#[allow(dead_code)]
+pub struct TickStream {
+    next: u64,
+}
+
+#[allow(dead_code)]
+impl TickStream {
+    pub fn new() -> Self {
+        TickStream { next: ticks() + 1 }
+    }
+
+    pub async fn next(&mut self) -> u64 {
+        sleep_until(self.next).await;
+        let now = ticks();
+        self.next = now + 1;
+        now
+    }
+}
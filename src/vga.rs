@@ -1,6 +1,7 @@
+use alloc::boxed::Box;
 use core::fmt;
 use volatile::Volatile;
-use spin::Mutex;
+use crate::sync::IrqMutex;
 use lazy_static::lazy_static;
 
 #[allow(dead_code)]
@@ -25,6 +26,34 @@ pub enum Color {
     White = 15,
 }
 
+impl Color {
+    pub fn from_name(name: &str) -> Option<Color> {
+        const COLORS: [(&str, Color); 16] = [
+            ("black", Color::Black),
+            ("blue", Color::Blue),
+            ("green", Color::Green),
+            ("cyan", Color::Cyan),
+            ("red", Color::Red),
+            ("magenta", Color::Magenta),
+            ("brown", Color::Brown),
+            ("lightgray", Color::LightGray),
+            ("darkgray", Color::DarkGray),
+            ("lightblue", Color::LightBlue),
+            ("lightgreen", Color::LightGreen),
+            ("lightcyan", Color::LightCyan),
+            ("lightred", Color::LightRed),
+            ("pink", Color::Pink),
+            ("yellow", Color::Yellow),
+            ("white", Color::White),
+        ];
+
+        COLORS
+            .iter()
+            .find(|(color_name, _)| color_name.eq_ignore_ascii_case(name))
+            .map(|&(_, color)| color)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 pub struct ColorCode(u8);
@@ -33,6 +62,34 @@ impl ColorCode {
     pub fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
+
+    pub fn inverted(self) -> ColorCode {
+        // Keep bit 7 clear so the swapped foreground doesn't turn on blinking.
+        ColorCode((self.0 & 0x07) << 4 | (self.0 >> 4))
+    }
+}
+
+// The code page 437 byte that shows `c`, or a block for characters it lacks.
+pub fn cp437(c: char) -> u8 {
+    const EXTENDED: [(char, u8); 52] = [
+        ('Ç', 0x80), ('ü', 0x81), ('é', 0x82), ('â', 0x83), ('ä', 0x84), ('à', 0x85),
+        ('å', 0x86), ('ç', 0x87), ('ê', 0x88), ('ë', 0x89), ('è', 0x8a), ('ï', 0x8b),
+        ('î', 0x8c), ('ì', 0x8d), ('Ä', 0x8e), ('Å', 0x8f), ('É', 0x90), ('æ', 0x91),
+        ('Æ', 0x92), ('ô', 0x93), ('ö', 0x94), ('ò', 0x95), ('û', 0x96), ('ù', 0x97),
+        ('ÿ', 0x98), ('Ö', 0x99), ('Ü', 0x9a), ('¢', 0x9b), ('£', 0x9c), ('¥', 0x9d),
+        ('á', 0xa0), ('í', 0xa1), ('ó', 0xa2), ('ú', 0xa3), ('ñ', 0xa4), ('Ñ', 0xa5),
+        ('¿', 0xa8), ('¬', 0xaa), ('½', 0xab), ('¼', 0xac), ('¡', 0xad), ('«', 0xae),
+        ('»', 0xaf), ('ß', 0xe1), ('µ', 0xe6), ('±', 0xf1), ('÷', 0xf6), ('°', 0xf8),
+        ('·', 0xfa), ('²', 0xfd), ('§', 0x15), ('¶', 0x14),
+    ];
+
+    match c {
+        ' '..='~' | '\n' => c as u8,
+        _ => EXTENDED
+            .iter()
+            .find(|&&(character, _)| character == c)
+            .map_or(0xfe, |&(_, byte)| byte),
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -45,15 +102,21 @@ struct ScreenChar {
 const BUFFER_HEIGHT: usize = 25;
 const BUFFER_WIDTH: usize = 80;
 
+pub const CONSOLE_COUNT: usize = 6;
+
 #[repr(transparent)]
 struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+type ScreenBuffer = [[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT];
+
 pub struct Writer {
     pub column_position: usize,
     pub color_code: ColorCode,
     buffer: &'static mut Buffer,
+    active_console: usize,
+    consoles: [Option<Box<ScreenBuffer>>; CONSOLE_COUNT],
 }
 
 impl Writer {
@@ -82,6 +145,127 @@ impl Writer {
             color_code,
         });
     }
+
+    pub fn scroll_region(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        let right = (x + width).min(BUFFER_WIDTH);
+        let bottom = (y + height).min(BUFFER_HEIGHT);
+        if x >= right || y >= bottom {
+            return;
+        }
+
+        for row in (y + 1)..bottom {
+            for col in x..right {
+                let character = self.buffer.chars[row][col].read();
+                self.buffer.chars[row - 1][col].write(character);
+            }
+        }
+
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        for col in x..right {
+            self.buffer.chars[bottom - 1][col].write(blank);
+        }
+    }
+}
+
+impl Writer {
+    pub fn active_console(&self) -> usize {
+        self.active_console
+    }
+
+    pub fn switch_console(&mut self, console: usize) {
+        if console == self.active_console || console >= CONSOLE_COUNT {
+            return;
+        }
+
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+
+        if let Some(saved) = self.consoles[self.active_console].as_deref_mut() {
+            for (row, saved_row) in saved.iter_mut().enumerate() {
+                for (col, saved_char) in saved_row.iter_mut().enumerate() {
+                    *saved_char = self.buffer.chars[row][col].read();
+                }
+            }
+        }
+
+        let restored = self.consoles[console].as_deref();
+        for row in 0..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                let character = restored.map_or(blank, |screen| screen[row][col]);
+                self.buffer.chars[row][col].write(character);
+            }
+        }
+        self.active_console = console;
+    }
+
+    // Buffers only exist once `init` has run, since nothing may allocate
+    // while the writer is locked.
+    fn console_screen(&mut self, console: usize) -> Option<&mut ScreenBuffer> {
+        self.consoles.get_mut(console)?.as_deref_mut()
+    }
+
+    pub fn console_write_char_at(&mut self, console: usize, x: usize, y: usize, byte: u8) {
+        if console == self.active_console {
+            self.write_char_at(x, y, byte);
+            return;
+        }
+        if x >= BUFFER_WIDTH || y >= BUFFER_HEIGHT {
+            return;
+        }
+
+        let color_code = self.color_code;
+        if let Some(screen) = self.console_screen(console) {
+            screen[y][x] = ScreenChar {
+                ascii_character: byte,
+                color_code,
+            };
+        }
+    }
+
+    pub fn console_scroll_region(&mut self, console: usize, x: usize, y: usize, width: usize, height: usize) {
+        if console == self.active_console {
+            self.scroll_region(x, y, width, height);
+            return;
+        }
+        let right = (x + width).min(BUFFER_WIDTH);
+        let bottom = (y + height).min(BUFFER_HEIGHT);
+        if x >= right || y >= bottom {
+            return;
+        }
+
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        let screen = match self.console_screen(console) {
+            Some(screen) => screen,
+            None => return,
+        };
+        for row in (y + 1)..bottom {
+            let line = screen[row];
+            screen[row - 1][x..right].copy_from_slice(&line[x..right]);
+        }
+        screen[bottom - 1][x..right].fill(blank);
+    }
+
+    pub fn console_clear(&mut self, console: usize) {
+        if console == self.active_console {
+            self.clear_screen();
+            return;
+        }
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        if let Some(screen) = self.console_screen(console) {
+            *screen = [[blank; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        }
+    }
 }
 
 impl Writer {
@@ -107,11 +291,8 @@ impl Writer {
     }
 
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                _ => self.write_byte(0xfe),
-            }
+        for c in s.chars() {
+            self.write_byte(cp437(c));
         }
     }
 
@@ -145,13 +326,27 @@ impl fmt::Write for Writer {
 }
 
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
+    // Interrupt-safe so handlers and the panic path can still print.
+    pub static ref WRITER: IrqMutex<Writer> = IrqMutex::new(Writer {
         column_position: 0,
         color_code: ColorCode::new(Color::Cyan, Color::Black),
         buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+        active_console: 0,
+        consoles: Default::default(),
     });
 }
 
+// Allocates the saved screens of the virtual consoles. Needs the heap.
+pub fn init() {
+    let color_code = WRITER.lock().color_code;
+    let blank = ScreenChar {
+        ascii_character: b' ',
+        color_code,
+    };
+    let consoles = core::array::from_fn(|_| Some(Box::new([[blank; BUFFER_WIDTH]; BUFFER_HEIGHT])));
+    WRITER.lock().consoles = consoles;
+}
+
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => ($crate::vga::_print(format_args!($($arg)*)));
@@ -0,0 +1,331 @@
+use crate::events::Event;
+use crate::keyboard::KeyEvent;
+use crate::vga::{Color, ColorCode};
+use crate::window_manager::{WindowHandle, WINDOW_MANAGER};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use pc_keyboard::KeyCode;
+use crate::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+pub type Callback = Box<dyn FnMut(&mut Form, usize) + Send>;
+
+pub enum WidgetKind {
+    Label {
+        text: String,
+    },
+    Button {
+        label: String,
+    },
+    TextField {
+        text: String,
+        width: usize,
+    },
+    Checkbox {
+        label: String,
+        checked: bool,
+    },
+    List {
+        items: Vec<String>,
+        selected: usize,
+        offset: usize,
+        width: usize,
+        height: usize,
+    },
+    ProgressBar {
+        value: usize,
+        max: usize,
+        width: usize,
+    },
+}
+
+impl WidgetKind {
+    pub fn label(text: &str) -> Self {
+        WidgetKind::Label { text: String::from(text) }
+    }
+
+    pub fn button(label: &str) -> Self {
+        WidgetKind::Button { label: String::from(label) }
+    }
+
+    pub fn text_field(width: usize) -> Self {
+        WidgetKind::TextField { text: String::new(), width }
+    }
+
+    pub fn checkbox(label: &str, checked: bool) -> Self {
+        WidgetKind::Checkbox { label: String::from(label), checked }
+    }
+
+    pub fn list(items: Vec<String>, width: usize, height: usize) -> Self {
+        WidgetKind::List { items, selected: 0, offset: 0, width, height }
+    }
+
+    pub fn progress_bar(width: usize) -> Self {
+        WidgetKind::ProgressBar { value: 0, max: 100, width }
+    }
+
+    fn focusable(&self) -> bool {
+        !matches!(self, WidgetKind::Label { .. } | WidgetKind::ProgressBar { .. })
+    }
+}
+
+pub struct Widget {
+    x: usize,
+    y: usize,
+    kind: WidgetKind,
+    callback: Option<Callback>,
+}
+
+impl Widget {
+    // Each widget renders to one or more rows of (text, highlighted) pairs.
+    fn render(&self, focused: bool) -> Vec<(String, bool)> {
+        match &self.kind {
+            WidgetKind::Label { text } => Vec::from([(text.clone(), false)]),
+            WidgetKind::Button { label } => Vec::from([(format!("[ {} ]", label), focused)]),
+            WidgetKind::TextField { text, width } => {
+                // The tail that fits, cut on a character boundary.
+                let hidden = text.chars().count().saturating_sub(width.saturating_sub(1));
+                let visible = text.char_indices().nth(hidden).map_or("", |(start, _)| &text[start..]);
+                Vec::from([(format!("{:_<width$}", visible, width = width), focused)])
+            },
+            WidgetKind::Checkbox { label, checked } => {
+                let mark = if *checked { 'x' } else { ' ' };
+                Vec::from([(format!("[{}] {}", mark, label), focused)])
+            },
+            WidgetKind::List { items, selected, offset, width, height } => (0..*height)
+                .map(|row| {
+                    let index = offset + row;
+                    let item = items.get(index).map_or("", |item| item.as_str());
+                    let line: String = item.chars().take(*width).collect();
+                    (format!("{:<width$}", line, width = width), index == *selected && index < items.len())
+                })
+                .collect(),
+            WidgetKind::ProgressBar { value, max, width } => {
+                let inner = width.saturating_sub(7);
+                let filled = if *max == 0 { 0 } else { (*value).min(*max) * inner / max };
+                let percent = if *max == 0 { 0 } else { (*value).min(*max) * 100 / max };
+                let bar: String = (0..inner).map(|i| if i < filled { '#' } else { ' ' }).collect();
+                Vec::from([(format!("[{}] {:>3}%", bar, percent), false)])
+            },
+        }
+    }
+
+    // Returns whether the key activates the widget's callback.
+    fn handle_key(&mut self, key: Key) -> bool {
+        match (&mut self.kind, key) {
+            (WidgetKind::Button { .. }, Key::Char('\n' | ' ')) => true,
+            (WidgetKind::Checkbox { checked, .. }, Key::Char('\n' | ' ')) => {
+                *checked = !*checked;
+                true
+            },
+            (WidgetKind::TextField { .. }, Key::Char('\n')) => true,
+            (WidgetKind::TextField { text, .. }, Key::Char('\u{0008}')) => {
+                text.pop();
+                false
+            },
+            (WidgetKind::TextField { text, .. }, Key::Char(c)) if !c.is_control() => {
+                text.push(c);
+                false
+            },
+            (WidgetKind::List { items, .. }, Key::Char('\n')) => !items.is_empty(),
+            (WidgetKind::List { selected, offset, .. }, Key::Up) => {
+                *selected = selected.saturating_sub(1);
+                *offset = (*offset).min(*selected);
+                false
+            },
+            (WidgetKind::List { items, selected, offset, height, .. }, Key::Down) => {
+                if *selected + 1 < items.len() {
+                    *selected += 1;
+                }
+                if *selected >= *offset + *height {
+                    *offset = *selected + 1 - *height;
+                }
+                false
+            },
+            _ => false,
+        }
+    }
+}
+
+// A set of widgets laid out in a window, with one focused widget receiving keys.
+pub struct Form {
+    pub window: WindowHandle,
+    widgets: Vec<Widget>,
+    focus: Option<usize>,
+}
+
+impl Form {
+    pub fn new(window: WindowHandle) -> Self {
+        Form {
+            window,
+            widgets: Vec::new(),
+            focus: None,
+        }
+    }
+
+    pub fn add(&mut self, x: usize, y: usize, kind: WidgetKind) -> usize {
+        if self.focus.is_none() && kind.focusable() {
+            self.focus = Some(self.widgets.len());
+        }
+        self.widgets.push(Widget { x, y, kind, callback: None });
+        self.widgets.len() - 1
+    }
+
+    pub fn on_activate(&mut self, index: usize, callback: Callback) {
+        if let Some(widget) = self.widgets.get_mut(index) {
+            widget.callback = Some(callback);
+        }
+    }
+
+    pub fn text(&self, index: usize) -> Option<&str> {
+        match &self.widgets.get(index)?.kind {
+            WidgetKind::TextField { text, .. } | WidgetKind::Label { text } => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn set_text(&mut self, index: usize, value: &str) {
+        match self.widgets.get_mut(index).map(|widget| &mut widget.kind) {
+            Some(WidgetKind::TextField { text, .. }) | Some(WidgetKind::Label { text }) => *text = String::from(value),
+            Some(WidgetKind::Button { label }) | Some(WidgetKind::Checkbox { label, .. }) => *label = String::from(value),
+            _ => {},
+        }
+    }
+
+    pub fn checked(&self, index: usize) -> bool {
+        matches!(self.widgets.get(index).map(|widget| &widget.kind), Some(WidgetKind::Checkbox { checked: true, .. }))
+    }
+
+    pub fn selected(&self, index: usize) -> Option<usize> {
+        match &self.widgets.get(index)?.kind {
+            WidgetKind::List { items, selected, .. } if *selected < items.len() => Some(*selected),
+            _ => None,
+        }
+    }
+
+    pub fn set_items(&mut self, index: usize, new_items: Vec<String>) {
+        if let Some(WidgetKind::List { items, selected, offset, .. }) = self.widgets.get_mut(index).map(|widget| &mut widget.kind) {
+            *items = new_items;
+            *selected = (*selected).min(items.len().saturating_sub(1));
+            *offset = (*offset).min(*selected);
+        }
+    }
+
+    pub fn set_progress(&mut self, index: usize, new_value: usize, new_max: usize) {
+        if let Some(WidgetKind::ProgressBar { value, max, .. }) = self.widgets.get_mut(index).map(|widget| &mut widget.kind) {
+            *value = new_value;
+            *max = new_max;
+        }
+    }
+
+    fn focus_next(&mut self) {
+        let count = self.widgets.len();
+        let start = self.focus.map_or(0, |focus| focus + 1);
+        self.focus = (0..count)
+            .map(|i| (start + i) % count)
+            .find(|&i| self.widgets[i].kind.focusable());
+    }
+
+    pub fn render(&self) {
+        let color = self.window.color().unwrap_or(Color::White);
+        let normal = ColorCode::new(color, Color::Black);
+        let highlight = ColorCode::new(Color::Black, color);
+
+        crate::window_manager::batch(|| {
+            for (index, widget) in self.widgets.iter().enumerate() {
+                for (row, (text, highlighted)) in widget.render(self.focus == Some(index)).iter().enumerate() {
+                    let color = if *highlighted { highlight } else { normal };
+                    self.window.print_colored_at(widget.x, widget.y + row, text, color);
+                }
+            }
+        });
+    }
+
+    fn handle_key(&mut self, key: Key) {
+        if key == Key::Char('\t') {
+            self.focus_next();
+            self.render();
+            return;
+        }
+
+        let index = match self.focus {
+            Some(index) => index,
+            None => return,
+        };
+        if self.widgets[index].handle_key(key) {
+            if let Some(mut callback) = self.widgets[index].callback.take() {
+                callback(self, index);
+                if let Some(widget) = self.widgets.get_mut(index) {
+                    widget.callback.get_or_insert(callback);
+                }
+            }
+        }
+        self.render();
+    }
+}
+
+lazy_static! {
+    static ref FORMS: Mutex<Vec<Form>> = Mutex::new(Vec::new());
+}
+
+// Shows a form and routes keys to it while its window has focus.
+pub fn open(form: Form) {
+    form.render();
+    let mut forms = FORMS.lock();
+    let manager = WINDOW_MANAGER.lock();
+    forms.retain(|other| manager.get(other.window).is_some() && other.window != form.window);
+    forms.push(form);
+}
+
+fn key_for(key: KeyEvent) -> Option<Key> {
+    if !key.pressed {
+        return None;
+    }
+    match key.code {
+        KeyCode::ArrowUp => Some(Key::Up),
+        KeyCode::ArrowDown => Some(Key::Down),
+        KeyCode::ArrowLeft => Some(Key::Left),
+        KeyCode::ArrowRight => Some(Key::Right),
+        _ => key.typed_char().map(Key::Char),
+    }
+}
+
+// Feeds each form the events queued for its window. A form is taken out of
+// the list while it runs so callbacks can open further forms.
+pub fn process_events() {
+    let windows: Vec<WindowHandle> = FORMS.lock().iter().map(|form| form.window).collect();
+    for window in windows {
+        while let Some(event) = window.poll_event() {
+            let mut forms = FORMS.lock();
+            let mut form = match forms.iter().position(|form| form.window == window) {
+                Some(index) => forms.remove(index),
+                None => break,
+            };
+            drop(forms);
+
+            match event {
+                Event::Key(key) => {
+                    if let Some(key) = key_for(key) {
+                        form.handle_key(key);
+                    }
+                },
+                Event::Resized(..) => form.render(),
+                _ => {},
+            }
+            if WINDOW_MANAGER.lock().get(form.window).is_some() {
+                FORMS.lock().push(form);
+            }
+        }
+    }
+}
@@ -1,5 +1,14 @@
-use crate::vga::{Color, ColorCode, WRITER};
-use spin::Mutex;
+use crate::compositor::{Cell, Layer, Surface, COMPOSITOR, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::config::{self, UiMode};
+use crate::events::{self, Event};
+use crate::mouse::{MouseEvent, BUTTON_LEFT};
+use crate::time;
+use crate::vga::{self, Color, ColorCode};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::sync::Mutex;
 
 const TOP_LEFT: u8 = b'+';
 const TOP_RIGHT: u8 = b'+';
@@ -7,157 +16,826 @@ const BOTTOM_LEFT: u8 = b'+';
 const BOTTOM_RIGHT: u8 = b'+';
 const HORIZONTAL: u8 = b'-';
 const VERTICAL: u8 = b'|';
+const CLOSE_BUTTON: u8 = b'x';
+
+pub const MAX_WINDOWS: usize = 10;
+const TASKBAR_ROW: usize = SCREEN_HEIGHT - 1;
+const TASKBAR_TITLE_LENGTH: usize = 10;
+const MIN_WIDTH: usize = 8;
+const MIN_HEIGHT: usize = 3;
+const INACTIVE_FRAME_COLOR: Color = Color::DarkGray;
+const GRABBED_FRAME_COLOR: Color = Color::Yellow;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowAction {
+    Move(isize, isize),
+    Resize(isize, isize),
+    Minimize,
+    Maximize,
+    Close,
+    CycleFocus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    Floating,
+    MasterStack,
+    Grid,
+}
+
+impl Layout {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layout::Floating => "floating",
+            Layout::MasterStack => "master",
+            Layout::Grid => "grid",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        [Layout::Floating, Layout::MasterStack, Layout::Grid]
+            .into_iter()
+            .find(|layout| layout.name() == name)
+    }
+}
+
+enum Hit {
+    Close(WindowHandle),
+    TitleBar(WindowHandle),
+    Body(WindowHandle),
+}
 
-#[derive(Debug, Clone, Copy)]
 pub struct Window {
     pub x: usize,
     pub y: usize,
     pub width: usize,
     pub height: usize,
-    pub title: &'static str,
+    pub title: String,
     pub color: Color,
+    pub minimized: bool,
+    restore: Option<(usize, usize, usize, usize)>,
+    // Geometry to return to when tiling is switched off.
+    floating: Option<(usize, usize, usize, usize)>,
+    surface: Surface,
 }
 
 impl Window {
-    pub fn new(x: usize, y: usize, width: usize, height: usize, title: &'static str, color: Color) -> Self {
-        Window {
+    pub fn new(x: usize, y: usize, width: usize, height: usize, title: &str, color: Color) -> Self {
+        let mut window = Window {
             x,
             y,
             width,
             height,
-            title,
+            title: String::from(title),
             color,
-        }
+            minimized: false,
+            restore: None,
+            floating: None,
+            surface: Surface::new(width, height),
+        };
+        window.draw();
+        window
     }
 
-    pub fn draw(&self) {
-        let mut writer = WRITER.lock();
-        let color_code = ColorCode::new(self.color, Color::Black);
-        let original_color = writer.color_code;
-        
-        writer.color_code = color_code;
-        
-        writer.write_char_at(self.x, self.y, TOP_LEFT);
-        for x in (self.x + 1)..(self.x + self.width - 1) {
-            writer.write_char_at(x, self.y, HORIZONTAL);
-        }
-        writer.write_char_at(self.x + self.width - 1, self.y, TOP_RIGHT);
-        
-        if self.title.len() < self.width - 2 {
-            let title_start = self.x + (self.width - self.title.len()) / 2;
-            for (i, &byte) in self.title.as_bytes().iter().enumerate() {
-                writer.write_char_at(title_start + i, self.y, byte);
-            }
+    fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    fn blank(&self) -> Cell {
+        Cell::new(b' ', self.color)
+    }
+
+    fn frame_char_at(&self, col: usize, row: usize) -> Option<u8> {
+        let right = self.width - 1;
+        let bottom = self.height - 1;
+
+        if row == 0 && col == right - 1 && self.width > 4 {
+            return Some(CLOSE_BUTTON);
         }
-        
-        for y in (self.y + 1)..(self.y + self.height - 1) {
-            writer.write_char_at(self.x, y, VERTICAL);
-            writer.write_char_at(self.x + self.width - 1, y, VERTICAL);
-            
-            for x in (self.x + 1)..(self.x + self.width - 1) {
-                writer.write_char_at(x, y, b' ');
+
+        if row == 0 && self.title.len() < self.width - 2 {
+            let title_start = (self.width - self.title.len()) / 2;
+            if col >= title_start && col < title_start + self.title.len() {
+                return Some(self.title.as_bytes()[col - title_start]);
             }
         }
-        
-        writer.write_char_at(self.x, self.y + self.height - 1, BOTTOM_LEFT);
-        for x in (self.x + 1)..(self.x + self.width - 1) {
-            writer.write_char_at(x, self.y + self.height - 1, HORIZONTAL);
+
+        match (col == 0 || col == right, row == 0 || row == bottom) {
+            (true, true) if row == 0 => Some(if col == 0 { TOP_LEFT } else { TOP_RIGHT }),
+            (true, true) => Some(if col == 0 { BOTTOM_LEFT } else { BOTTOM_RIGHT }),
+            (true, false) => Some(VERTICAL),
+            (false, true) => Some(HORIZONTAL),
+            (false, false) => None,
         }
-        writer.write_char_at(self.x + self.width - 1, self.y + self.height - 1, BOTTOM_RIGHT);
-        
-        writer.color_code = original_color;
     }
 
-    pub fn clear(&self) {
-        let mut writer = WRITER.lock();
-        let original_color = writer.color_code;
-        writer.color_code = ColorCode::new(self.color, Color::Black);
-        
-        for y in (self.y + 1)..(self.y + self.height - 1) {
-            for x in (self.x + 1)..(self.x + self.width - 1) {
-                writer.write_char_at(x, y, b' ');
+    fn draw_frame(&mut self, frame_color: Color) {
+        for row in 0..self.height {
+            for col in 0..self.width {
+                if let Some(byte) = self.frame_char_at(col, row) {
+                    self.surface.set(col, row, Cell::new(byte, frame_color));
+                }
             }
         }
-        
-        writer.color_code = original_color;
     }
-    
-    pub fn print_at(&self, x_offset: usize, y_offset: usize, text: &str) {
-        let start_x = self.x + 1 + x_offset;
-        let start_y = self.y + 1 + y_offset;
-        
-        if start_y >= self.y + self.height - 1 {
-            return;
+
+    pub fn draw(&mut self) {
+        self.clear();
+        self.draw_frame(self.color);
+    }
+
+    pub fn clear(&mut self) {
+        let blank = self.blank();
+        self.surface.fill(1, 1, self.width - 2, self.height - 2, blank);
+    }
+
+    pub fn scroll_up(&mut self) {
+        let blank = self.blank();
+        self.surface.scroll_up(1, 1, self.width - 2, self.height - 2, blank);
+    }
+
+    pub fn print_at(&mut self, x_offset: usize, y_offset: usize, text: &str) {
+        self.print_colored_at(x_offset, y_offset, text, ColorCode::new(self.color, Color::Black));
+    }
+
+    pub fn print_colored_at(&mut self, x_offset: usize, y_offset: usize, text: &str, color: ColorCode) {
+        let row = match y_offset.checked_add(1) {
+            Some(row) if row < self.height - 1 => row,
+            _ => return,
+        };
+
+        for (i, byte) in text.chars().map(vga::cp437).enumerate() {
+            let col = match x_offset.checked_add(1 + i) {
+                Some(col) if col < self.width - 1 => col,
+                _ => break,
+            };
+            self.surface.set(col, row, Cell { byte, color });
         }
-        
-        let mut writer = WRITER.lock();
-        let original_color = writer.color_code;
-        writer.color_code = ColorCode::new(self.color, Color::Black);
-        
-        for (i, &byte) in text.as_bytes().iter().enumerate() {
-            let x = start_x + i;
-            if x >= self.x + self.width - 1 {
-                break;
-            }
-            if x < 80 && start_y < 25 {
-                writer.write_char_at(x, start_y, byte);
-            }
+    }
+
+    // Changes the geometry, keeping whatever part of the contents still fits.
+    fn reshape(&mut self, x: usize, y: usize, width: usize, height: usize, frame_color: Color) {
+        let (old_width, old_height) = (self.width, self.height);
+        self.x = x;
+        self.y = y;
+        self.width = width;
+        self.height = height;
+        if (width, height) == (old_width, old_height) {
+            return;
         }
-        
-        writer.color_code = original_color;
+
+        // The old right and bottom frame edges now sit inside or outside the window.
+        let blank = self.blank();
+        self.surface = self.surface.resized(width, height);
+        self.surface.fill(old_width - 1, 0, width, height, blank);
+        self.surface.fill(0, old_height - 1, width, height, blank);
+        self.draw_frame(frame_color);
+    }
+}
+
+// Refers to a window owned by the window manager. Slots are reused once a
+// window closes, so the generation stops stale handles reaching a new window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowHandle {
+    slot: usize,
+    generation: u32,
+}
+
+impl WindowHandle {
+    pub fn id(&self) -> usize {
+        self.slot
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Window)) {
+        WINDOW_MANAGER.lock().update(*self, f);
+    }
+
+    pub fn size(&self) -> Option<(usize, usize)> {
+        WINDOW_MANAGER.lock().get(*self).map(|window| (window.width, window.height))
+    }
+
+    pub fn clear(&self) {
+        self.update(|window| window.clear());
+    }
+
+    pub fn scroll_up(&self) {
+        self.update(|window| window.scroll_up());
+    }
+
+    pub fn print_at(&self, x_offset: usize, y_offset: usize, text: &str) {
+        self.update(|window| window.print_at(x_offset, y_offset, text));
+    }
+
+    pub fn print_colored_at(&self, x_offset: usize, y_offset: usize, text: &str, color: ColorCode) {
+        self.update(|window| window.print_colored_at(x_offset, y_offset, text, color));
+    }
+
+    pub fn color(&self) -> Option<Color> {
+        WINDOW_MANAGER.lock().get(*self).map(|window| window.color)
+    }
+
+    pub fn set_title(&self, title: &str) {
+        WINDOW_MANAGER.lock().set_title(*self, title);
+    }
+
+    // Events go through a lock-free queue, so they can be posted from
+    // interrupt handlers without taking the window manager lock.
+    pub fn post_event(&self, event: Event) {
+        events::post(self.slot, self.generation, event);
+    }
+
+    pub fn poll_event(&self) -> Option<Event> {
+        events::poll(self.slot, self.generation)
+    }
+
+    // Blocks the calling thread until an event is posted, the window closes or
+    // `wake_waiters` is called. Wakeups may be spurious.
+    pub fn wait_event(&self) {
+        events::wait(self.slot);
+    }
+
+    pub fn wake_waiters(&self) {
+        events::wake(self.slot);
     }
 }
 
 pub struct WindowManager {
-    windows: [Option<Window>; 10],
-    active_window: usize,
+    windows: [Option<Window>; MAX_WINDOWS],
+    generations: [u32; MAX_WINDOWS],
+    // Window slots from bottom to top; the topmost visible window has focus.
+    z_order: Vec<usize>,
+    taskbar: Surface,
+    layout: Layout,
+    // Window last told it has focus.
+    focused: Option<WindowHandle>,
+    grabbed: bool,
+    // Window being dragged by its title bar, with the grab offset.
+    drag: Option<(WindowHandle, usize, usize)>,
 }
 
 impl WindowManager {
     pub fn new() -> Self {
         WindowManager {
-            windows: [None; 10],
-            active_window: 0,
+            windows: Default::default(),
+            generations: [0; MAX_WINDOWS],
+            z_order: Vec::new(),
+            taskbar: Surface::new(SCREEN_WIDTH, 1),
+            layout: Layout::Floating,
+            focused: None,
+            grabbed: false,
+            drag: None,
         }
     }
     
-    pub fn add_window(&mut self, window: Window) -> Option<usize> {
-        for (i, slot) in self.windows.iter_mut().enumerate() {
-            if slot.is_none() {
-                *slot = Some(window);
-                return Some(i);
-            }
+    pub fn add_window(&mut self, window: Window) -> Option<WindowHandle> {
+        let slot = self.windows.iter().position(|slot| slot.is_none())?;
+        let previous = self.active_window();
+
+        self.windows[slot] = Some(window);
+        self.z_order.push(slot);
+        self.restack(previous);
+        self.arrange();
+
+        self.handle(slot)
+    }
+
+    pub fn remove_window(&mut self, handle: WindowHandle) -> Option<Window> {
+        self.get(handle)?;
+        let previous = self.active_window().filter(|&previous| previous != handle);
+
+        let window = self.windows[handle.slot].take();
+        self.generations[handle.slot] = self.generations[handle.slot].wrapping_add(1);
+        events::wake(handle.slot);
+        self.z_order.retain(|&slot| slot != handle.slot);
+        self.restack(previous);
+        self.arrange();
+
+        window
+    }
+
+    // Looks up the current window in a slot, as shown by `window list`.
+    pub fn handle(&self, slot: usize) -> Option<WindowHandle> {
+        self.windows.get(slot)?.as_ref()?;
+        Some(WindowHandle { slot, generation: self.generations[slot] })
+    }
+    
+    pub fn get(&self, handle: WindowHandle) -> Option<&Window> {
+        if self.generations.get(handle.slot) != Some(&handle.generation) {
+            return None;
+        }
+        self.windows[handle.slot].as_ref()
+    }
+
+    fn get_mut(&mut self, handle: WindowHandle) -> Option<&mut Window> {
+        if self.generations.get(handle.slot) != Some(&handle.generation) {
+            return None;
         }
-        None
+        self.windows[handle.slot].as_mut()
+    }
+
+    pub fn windows(&self) -> impl Iterator<Item = (WindowHandle, &Window)> + '_ {
+        self.z_order.iter().filter_map(|&slot| {
+            let handle = self.handle(slot)?;
+            self.windows[slot].as_ref().map(|window| (handle, window))
+        })
     }
     
-    pub fn get_window(&self, id: usize) -> Option<Window> {
-        self.windows.get(id).and_then(|w| *w)
+    pub fn active_window(&self) -> Option<WindowHandle> {
+        self.windows().filter(|(_, window)| !window.minimized).map(|(handle, _)| handle).last()
     }
     
-    pub fn set_active_window(&mut self, id: usize) {
-        if id < self.windows.len() && self.windows[id].is_some() {
-            self.active_window = id;
+    pub fn set_active_window(&mut self, handle: WindowHandle) {
+        let previous = self.active_window();
+        if previous == Some(handle) {
+            return;
+        }
+        let window = match self.get_mut(handle) {
+            Some(window) => window,
+            None => return,
+        };
+
+        let restored = core::mem::replace(&mut window.minimized, false);
+        self.z_order.retain(|&slot| slot != handle.slot);
+        self.z_order.push(handle.slot);
+        self.restack(previous);
+        if restored {
+            self.arrange();
+        }
+    }
+
+    pub fn cycle_focus(&mut self) {
+        if let Some(bottom) = self.z_order.first().and_then(|&slot| self.handle(slot)) {
+            self.set_active_window(bottom);
         }
     }
 
-    pub fn draw_all(&self) {
-        let active = self.active_window;
-        
-        for (i, window) in self.windows.iter().enumerate() {
-            if i != active {
-                if let Some(window) = window {
-                    window.draw();
+    fn update(&mut self, handle: WindowHandle, f: impl FnOnce(&mut Window)) {
+        if let Some(window) = self.get_mut(handle) {
+            f(window);
+            let (x, y, width, height) = (window.x, window.y, window.width, window.height);
+            COMPOSITOR.lock().mark_dirty(x, y, width, height);
+            self.flush();
+        }
+    }
+
+    pub fn set_title(&mut self, handle: WindowHandle, title: &str) {
+        let frame_color = self.frame_color(handle);
+        if let Some(window) = self.get_mut(handle) {
+            window.title = String::from(title);
+        }
+        self.draw_taskbar();
+        self.update(handle, |window| {
+            // Repaint the whole title bar so no characters of the old title remain.
+            window.surface.fill(1, 0, window.width - 2, 1, Cell::new(HORIZONTAL, frame_color));
+            window.draw_frame(frame_color);
+        });
+    }
+
+    pub fn set_grabbed(&mut self, grabbed: bool) {
+        self.grabbed = grabbed;
+        if let Some(handle) = self.active_window() {
+            let frame_color = self.frame_color(handle);
+            self.update(handle, |window| window.draw_frame(frame_color));
+        }
+    }
+
+    fn frame_color(&self, handle: WindowHandle) -> Color {
+        let color = self.get(handle).map_or(Color::White, |window| window.color);
+        match (self.active_window() == Some(handle), self.grabbed) {
+            (true, true) => GRABBED_FRAME_COLOR,
+            (true, false) => color,
+            (false, _) => INACTIVE_FRAME_COLOR,
+        }
+    }
+
+    // Clamps the geometry to the screen above the taskbar; returns whether the window's size changed.
+    fn set_geometry(&mut self, handle: WindowHandle, x: usize, y: usize, width: usize, height: usize) -> bool {
+        let frame_color = self.frame_color(handle);
+        let window = match self.get_mut(handle) {
+            Some(window) => window,
+            None => return false,
+        };
+
+        let old_size = (window.width, window.height);
+        let width = width.clamp(MIN_WIDTH.min(window.width), SCREEN_WIDTH);
+        let height = height.clamp(MIN_HEIGHT.min(window.height), TASKBAR_ROW);
+        window.reshape(x.min(SCREEN_WIDTH - width), y.min(TASKBAR_ROW - height), width, height, frame_color);
+
+        self.restack(None);
+        let resized = old_size != (width, height);
+        if resized {
+            handle.post_event(Event::Resized(width, height));
+        }
+        resized
+    }
+
+    // Tiled windows keep their tiles; moving and resizing only apply when floating.
+    pub fn move_window(&mut self, handle: WindowHandle, dx: isize, dy: isize) -> bool {
+        if self.layout != Layout::Floating {
+            return false;
+        }
+        let (x, y, width, height) = match self.get(handle) {
+            Some(window) => (window.x, window.y, window.width, window.height),
+            None => return false,
+        };
+        self.set_geometry(handle, x.saturating_add_signed(dx), y.saturating_add_signed(dy), width, height)
+    }
+
+    pub fn resize_window(&mut self, handle: WindowHandle, dw: isize, dh: isize) -> bool {
+        if self.layout != Layout::Floating {
+            return false;
+        }
+        let (x, y, width, height) = match self.get(handle) {
+            Some(window) => (window.x, window.y, window.width, window.height),
+            None => return false,
+        };
+        self.set_geometry(handle, x, y, width.saturating_add_signed(dw), height.saturating_add_signed(dh))
+    }
+
+    pub fn toggle_maximize(&mut self, handle: WindowHandle) -> bool {
+        let window = match self.get_mut(handle) {
+            Some(window) => window,
+            None => return false,
+        };
+        let (x, y, width, height) = match window.restore.take() {
+            Some(geometry) => geometry,
+            None => {
+                window.restore = Some((window.x, window.y, window.width, window.height));
+                (0, 0, SCREEN_WIDTH, TASKBAR_ROW)
+            },
+        };
+        self.set_geometry(handle, x, y, width, height)
+    }
+
+    pub fn minimize_window(&mut self, handle: WindowHandle) {
+        let previous = self.active_window();
+        if let Some(window) = self.get_mut(handle) {
+            window.minimized = true;
+            self.z_order.retain(|&slot| slot != handle.slot);
+            self.z_order.insert(0, handle.slot);
+            self.restack(previous);
+            self.arrange();
+        }
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+        if layout != Layout::Floating {
+            self.arrange();
+            return;
+        }
+
+        COMPOSITOR.lock().defer();
+        for slot in 0..MAX_WINDOWS {
+            let handle = match self.handle(slot) {
+                Some(handle) => handle,
+                None => continue,
+            };
+            if let Some((x, y, width, height)) = self.get_mut(handle).and_then(|window| window.floating.take()) {
+                self.set_geometry(handle, x, y, width, height);
+            }
+        }
+        COMPOSITOR.lock().resume();
+        self.flush();
+    }
+
+    // Tiles the visible windows in slot order, so the first one is the master.
+    fn arrange(&mut self) {
+        if self.layout == Layout::Floating {
+            return;
+        }
+        let handles: Vec<WindowHandle> = (0..MAX_WINDOWS)
+            .filter_map(|slot| self.handle(slot))
+            .filter(|&handle| self.get(handle).is_some_and(|window| !window.minimized))
+            .collect();
+        let tiles = tiles(self.layout, handles.len());
+
+        COMPOSITOR.lock().defer();
+        for (handle, (x, y, width, height)) in handles.into_iter().zip(tiles) {
+            if let Some(window) = self.get_mut(handle) {
+                window.restore = None;
+                if window.floating.is_none() {
+                    window.floating = Some((window.x, window.y, window.width, window.height));
                 }
             }
+            self.set_geometry(handle, x, y, width, height);
+        }
+        COMPOSITOR.lock().resume();
+        self.flush();
+    }
+
+    // Window labels on the taskbar, in slot order, with their start columns.
+    fn taskbar_entries(&self) -> Vec<(usize, WindowHandle, String)> {
+        let mut col = 0;
+        let mut entries = Vec::new();
+        for (slot, window) in self.windows.iter().enumerate() {
+            let (handle, window) = match (self.handle(slot), window) {
+                (Some(handle), Some(window)) => (handle, window),
+                _ => continue,
+            };
+            let title: String = window.title.chars().take(TASKBAR_TITLE_LENGTH).collect();
+            let label = format!(" {}:{} ", slot, title);
+            let len = label.len();
+            entries.push((col, handle, label));
+            col += len + 1;
+        }
+        entries
+    }
+
+    // Redraws the bottom row: the window list on the left, system indicators on the right.
+    fn draw_taskbar(&mut self) {
+        let background = ColorCode::new(Color::White, Color::Blue);
+        self.taskbar.fill(0, 0, SCREEN_WIDTH, 1, Cell { byte: b' ', color: background });
+
+        let (used, size) = crate::allocator::heap_usage();
+        let seconds = crate::time::uptime_seconds();
+        let status = format!(
+            " Heap {}K/{}K | {} | {:02}:{:02}:{:02} ",
+            used / 1024,
+            size / 1024,
+            config::get_current_ui_mode().name(),
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        );
+        let status_start = SCREEN_WIDTH.saturating_sub(status.len());
+        for (i, byte) in status.bytes().enumerate() {
+            self.taskbar.set(status_start + i, 0, Cell { byte, color: background });
+        }
+
+        let active = self.active_window();
+        for (start, handle, label) in self.taskbar_entries() {
+            let color = match self.get(handle) {
+                _ if Some(handle) == active => ColorCode::new(Color::Black, Color::LightGray),
+                Some(window) if window.minimized => ColorCode::new(Color::DarkGray, Color::Blue),
+                _ => background,
+            };
+            for (i, byte) in label.bytes().enumerate().take_while(|(i, _)| start + i < status_start) {
+                self.taskbar.set(start + i, 0, Cell { byte, color });
+            }
+        }
+        COMPOSITOR.lock().mark_dirty(0, TASKBAR_ROW, SCREEN_WIDTH, 1);
+    }
+
+    fn hit_test(&self, x: usize, y: usize) -> Option<Hit> {
+        if y == TASKBAR_ROW {
+            return self
+                .taskbar_entries()
+                .into_iter()
+                .find(|(start, _, label)| x >= *start && x < start + label.len())
+                .map(|(_, handle, _)| Hit::Body(handle));
+        }
+
+        let (handle, window) = self
+            .windows()
+            .filter(|(_, window)| !window.minimized && window.contains(x, y))
+            .last()?;
+        if y != window.y {
+            Some(Hit::Body(handle))
+        } else if window.frame_char_at(x - window.x, 0) == Some(CLOSE_BUTTON) {
+            Some(Hit::Close(handle))
+        } else {
+            Some(Hit::TitleBar(handle))
+        }
+    }
+
+    // Repaints the frames of the windows that lost and gained focus and
+    // recomposites the whole screen.
+    fn restack(&mut self, previous_active: Option<WindowHandle>) {
+        let active = self.active_window();
+        if active != self.focused {
+            if let Some(handle) = self.focused {
+                handle.post_event(Event::FocusLost);
+            }
+            if let Some(handle) = active {
+                handle.post_event(Event::FocusGained);
+            }
+            self.focused = active;
+        }
+
+        for handle in [previous_active, active].into_iter().flatten() {
+            let frame_color = self.frame_color(handle);
+            if let Some(window) = self.get_mut(handle) {
+                window.draw_frame(frame_color);
+            }
         }
-        
-        if let Some(window) = self.windows.get(active).and_then(|w| *w) {
-            window.draw();
+
+        self.draw_taskbar();
+        COMPOSITOR.lock().mark_dirty(0, 0, SCREEN_WIDTH, SCREEN_HEIGHT);
+        self.flush();
+    }
+
+    pub fn draw_all(&mut self) {
+        let handles: Vec<WindowHandle> = self.windows().map(|(handle, _)| handle).collect();
+        for handle in handles {
+            let frame_color = self.frame_color(handle);
+            if let Some(window) = self.get_mut(handle) {
+                window.draw_frame(frame_color);
+            }
         }
+
+        self.draw_taskbar();
+        COMPOSITOR.lock().invalidate();
+        self.flush();
+    }
+
+    fn flush(&self) {
+        let mut layers: Vec<Layer> = self
+            .windows()
+            .filter(|(_, window)| !window.minimized)
+            .map(|(_, window)| Layer { x: window.x, y: window.y, surface: &window.surface })
+            .collect();
+        layers.push(Layer { x: 0, y: TASKBAR_ROW, surface: &self.taskbar });
+        COMPOSITOR.lock().flush(&layers);
+    }
+}
+
+// Splits `total` cells into `parts` runs as evenly as possible, as (start, length).
+fn split(total: usize, parts: usize) -> impl Iterator<Item = (usize, usize)> {
+    let mut start = 0;
+    (0..parts).map(move |i| {
+        let length = total / parts + usize::from(i < total % parts);
+        start += length;
+        (start - length, length)
+    })
+}
+
+// Window geometries filling the screen above the taskbar.
+fn tiles(layout: Layout, count: usize) -> Vec<(usize, usize, usize, usize)> {
+    match (layout, count) {
+        (Layout::Floating, _) | (_, 0) => Vec::new(),
+        (_, 1) => vec![(0, 0, SCREEN_WIDTH, TASKBAR_ROW)],
+        // Too many windows to stack at their minimum height, so grid them instead.
+        (Layout::MasterStack, _) if count - 1 > TASKBAR_ROW / MIN_HEIGHT => tiles(Layout::Grid, count),
+        (Layout::MasterStack, _) => {
+            let master_width = SCREEN_WIDTH / 2;
+            let mut tiles = vec![(0, 0, master_width, TASKBAR_ROW)];
+            for (y, height) in split(TASKBAR_ROW, count - 1) {
+                tiles.push((master_width, y, SCREEN_WIDTH - master_width, height));
+            }
+            tiles
+        },
+        (Layout::Grid, _) => {
+            let columns = (1..).find(|columns| columns * columns >= count).unwrap_or(1);
+            let rows = count.div_ceil(columns);
+            let mut tiles = Vec::new();
+            for (row, (y, height)) in split(TASKBAR_ROW, rows).enumerate() {
+                // The last row may be short; its windows widen to fill it.
+                let in_row = columns.min(count - row * columns);
+                for (x, width) in split(SCREEN_WIDTH, in_row) {
+                    tiles.push((x, y, width, height));
+                }
+            }
+            tiles
+        },
     }
 }
 
 lazy_static::lazy_static! {
     pub static ref WINDOW_MANAGER: Mutex<WindowManager> = Mutex::new(WindowManager::new());
-}
\ No newline at end of file
+}
+
+pub fn reset() {
+    let mut manager = WINDOW_MANAGER.lock();
+    *manager = WindowManager::new();
+    *COMPOSITOR.lock() = crate::compositor::Compositor::new();
+    for slot in 0..MAX_WINDOWS {
+        events::wake(slot);
+    }
+}
+
+// Holds back screen updates while `f` draws, so only the finished result shows.
+pub fn batch<R>(f: impl FnOnce() -> R) -> R {
+    COMPOSITOR.lock().defer();
+    let result = f();
+    COMPOSITOR.lock().resume();
+    WINDOW_MANAGER.lock().flush();
+    result
+}
+
+// Keeps the taskbar clock and heap usage current.
+pub async fn run_taskbar_clock() {
+    loop {
+        let next_second = (time::uptime_seconds() + 1) * time::TICKS_PER_SECOND;
+        time::sleep_until(next_second).await;
+        if config::get_current_ui_mode() == UiMode::Windowed {
+            let mut manager = WINDOW_MANAGER.lock();
+            manager.draw_taskbar();
+            manager.flush();
+        }
+    }
+}
+
+pub fn cycle_focus() {
+    WINDOW_MANAGER.lock().cycle_focus();
+}
+
+pub fn set_grabbed(grabbed: bool) {
+    WINDOW_MANAGER.lock().set_grabbed(grabbed);
+}
+
+pub fn apply_to_active(action: WindowAction) {
+    let mut manager = WINDOW_MANAGER.lock();
+    let handle = match manager.active_window() {
+        Some(handle) => handle,
+        None => return,
+    };
+
+    match action {
+        WindowAction::Move(dx, dy) => {
+            manager.move_window(handle, dx, dy);
+        },
+        WindowAction::Resize(dw, dh) => {
+            manager.resize_window(handle, dw, dh);
+        },
+        WindowAction::Maximize => {
+            manager.toggle_maximize(handle);
+        },
+        WindowAction::Minimize => manager.minimize_window(handle),
+        WindowAction::CycleFocus => manager.cycle_focus(),
+        WindowAction::Close => {
+            drop(manager);
+            crate::terminal::close_window(handle);
+            dispatch_events();
+            return;
+        },
+    }
+
+    let grabbed = manager.grabbed;
+    manager.set_grabbed(grabbed);
+    drop(manager);
+    dispatch_events();
+}
+
+pub fn set_layout(layout: Layout) {
+    WINDOW_MANAGER.lock().set_layout(layout);
+    dispatch_events();
+}
+
+// Posts a key to the focused window.
+pub fn post_key(event: Event) {
+    if let Some(handle) = WINDOW_MANAGER.lock().active_window() {
+        handle.post_event(event);
+    }
+}
+
+// Lets forms and terminals drain their windows' queues. Other window owners,
+// such as the 3D demo, poll their own.
+pub fn dispatch_events() {
+    crate::widgets::process_events();
+    crate::terminal::process_events();
+}
+
+pub fn handle_mouse(event: MouseEvent) {
+    if config::get_current_ui_mode() != UiMode::Windowed {
+        return;
+    }
+
+    let mut manager = WINDOW_MANAGER.lock();
+    COMPOSITOR.lock().set_pointer(Some((event.x, event.y)));
+    manager.flush();
+
+    let under_pointer = manager
+        .windows()
+        .filter(|(_, window)| !window.minimized && window.contains(event.x, event.y))
+        .last()
+        .map(|(handle, window)| (handle, window.x, window.y));
+    if let Some((handle, x, y)) = under_pointer {
+        handle.post_event(Event::Mouse(MouseEvent { x: event.x - x, y: event.y - y, ..event }));
+    }
+
+    if event.pressed(BUTTON_LEFT) {
+        match manager.hit_test(event.x, event.y) {
+            Some(Hit::Close(handle)) => {
+                drop(manager);
+                crate::terminal::close_window(handle);
+                dispatch_events();
+                return;
+            },
+            Some(Hit::TitleBar(handle)) => {
+                manager.set_active_window(handle);
+                if let Some(window) = manager.get(handle).filter(|_| manager.layout == Layout::Floating) {
+                    manager.drag = Some((handle, event.x - window.x, event.y - window.y));
+                }
+            },
+            Some(Hit::Body(handle)) => manager.set_active_window(handle),
+            None => {},
+        }
+    } else if event.released(BUTTON_LEFT) {
+        manager.drag = None;
+    } else if let Some((handle, offset_x, offset_y)) = manager.drag {
+        let (x, y) = (event.x.saturating_sub(offset_x), event.y.saturating_sub(offset_y));
+        if let Some(window) = manager.get(handle) {
+            if (x, y) != (window.x, window.y) {
+                let (width, height) = (window.width, window.height);
+                manager.set_geometry(handle, x, y, width, height);
+            }
+        }
+    }
+    drop(manager);
+    dispatch_events();
+}